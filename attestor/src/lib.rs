@@ -27,6 +27,8 @@ use oracle::Oracle;
 
 use oracle::DbValue;
 
+use dlc_clients::EventMetadata;
+
 use dlc_messages::oracle_msgs::{
     DigitDecompositionEventDescriptor, EventDescriptor, OracleAnnouncement, OracleAttestation,
     OracleEvent,
@@ -133,7 +135,17 @@ impl Attestor {
             .event_handler
             .storage_api
             .clone()
-            .insert(uuid.to_string(), new_event.clone(), self.secret_key)
+            .insert(
+                uuid.to_string(),
+                new_event.clone(),
+                EventMetadata {
+                    maturity: Some(maturation.unix_timestamp()),
+                    attested: Some(false),
+                    outcome: None,
+                    chain: Some(chain.to_string()),
+                },
+                self.secret_key,
+            )
             .await
         {
             Ok(Some(_val)) => Ok(()),
@@ -236,7 +248,17 @@ impl Attestor {
             .oracle
             .event_handler
             .storage_api
-            .insert(uuid.clone(), new_event.clone(), self.secret_key)
+            .insert(
+                uuid.clone(),
+                new_event.clone(),
+                EventMetadata {
+                    maturity: Some(i64::from(announcement.oracle_event.event_maturity_epoch)),
+                    attested: Some(true),
+                    outcome: Some(outcome.to_string()),
+                    chain: event.5.clone(),
+                },
+                self.secret_key,
+            )
             .await
         {
             Ok(val) => val,
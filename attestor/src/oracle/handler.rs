@@ -1,7 +1,8 @@
 extern crate base64;
 use crate::oracle::OracleError;
 use dlc_clients::{
-    EventRequestParams, EventsRequestParams, NewEvent, StorageApiClient, UpdateEvent,
+    EventMetadata, EventRequestParams, EventsRequestParams, NewEvent, StorageApi, StorageApiClient,
+    UpdateEvent,
 };
 use secp256k1_zkp::SecretKey;
 
@@ -20,7 +21,11 @@ pub struct EventHandler {
 
 impl EventHandler {
     pub fn new(storage_api_endpoint: String, public_key: String) -> Self {
-        let storage_api_client = StorageApiClient::new(storage_api_endpoint);
+        // events hold the secret nonces of their announcements, their metadata is
+        // published in the announcements and attestations anyway
+        let storage_api_client = StorageApiClient::new(storage_api_endpoint)
+            .with_content_encryption()
+            .with_plaintext_metadata();
         let storage_api_conn = StorageApiConn::new(storage_api_client, public_key);
 
         Self {
@@ -29,22 +34,27 @@ impl EventHandler {
     }
 }
 
+/// Stores events through a [`StorageApi`], the storage API itself unless a test hands
+/// in a `dlc_clients::MemoryStorageApi`.
 #[derive(Debug, Clone)]
-pub struct StorageApiConn {
-    pub client: StorageApiClient,
+pub struct StorageApiConn<S = StorageApiClient> {
+    pub client: S,
     public_key: String,
 }
 
-impl StorageApiConn {
-    pub fn new(client: StorageApiClient, public_key: String) -> Self {
+impl<S: StorageApi> StorageApiConn<S> {
+    pub fn new(client: S, public_key: String) -> Self {
         Self { client, public_key }
     }
 
     // Todo: Remove upsert functionality for simplicity
+    /// Stores the event with `metadata` next to it, so the storage API can filter events
+    /// without decrypting them.
     pub async fn insert(
         &self,
         event_id: String,
         new_event: Vec<u8>,
+        metadata: EventMetadata,
         secret_key: SecretKey,
     ) -> Result<Option<Vec<u8>>, OracleError> {
         let new_content = base64::encode(new_event.clone());
@@ -71,6 +81,7 @@ impl StorageApiConn {
                 content: new_content.clone(),
                 event_id: event_id.clone(),
                 key: self.public_key.clone(),
+                metadata,
             };
             let res = self.client.update_event(update_event, secret_key).await;
             match res {
@@ -82,6 +93,7 @@ impl StorageApiConn {
                 event_id: event_id.clone(),
                 content: new_content.clone(),
                 key: self.public_key.clone(),
+                metadata,
             };
             let res = self.client.create_event(event, secret_key).await;
             match res {
@@ -125,7 +137,7 @@ impl StorageApiConn {
             .get_events(
                 EventsRequestParams {
                     key: self.public_key.clone(),
-                    event_id: None,
+                    ..Default::default()
                 },
                 secret_key,
             )
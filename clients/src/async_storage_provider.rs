@@ -9,14 +9,16 @@ use secp256k1_zkp::SecretKey;
 
 use crate::utils::{get_contract_id_string, to_storage_error};
 use crate::{
-    ApiError, Contract, ContractRequestParams, ContractsRequestParams, NewContract,
+    ApiError, Contract, ContractRequestParams, ContractsRequestParams, NewContract, StorageApi,
     StorageApiClient, UpdateContract,
 };
 
-use super::utils::{deserialize_contract, get_contract_state_str, serialize_contract};
+use super::utils::{
+    deserialize_contract, get_contract_metadata, get_contract_state_str, serialize_contract,
+};
 
-pub struct AsyncStorageApiProvider {
-    client: StorageApiClient,
+pub struct AsyncStorageApiProvider<S = StorageApiClient> {
+    client: S,
     public_key: String,
     secret_key: SecretKey, // hand in private and pub key, and do the signing here?
 }
@@ -30,6 +32,32 @@ impl AsyncStorageApiProvider {
         }
     }
 
+    /// Stores contracts encrypted, see [`StorageApiClient::with_content_encryption`].
+    pub fn with_content_encryption(mut self) -> Self {
+        self.client = self.client.with_content_encryption();
+        self
+    }
+
+    /// Sends the metadata of encrypted contracts anyway, see
+    /// [`StorageApiClient::with_plaintext_metadata`].
+    pub fn with_plaintext_metadata(mut self) -> Self {
+        self.client = self.client.with_plaintext_metadata();
+        self
+    }
+}
+
+impl<S: StorageApi> AsyncStorageApiProvider<S> {
+    /// Stores contracts through `client`, e.g. a [`MemoryStorageApi`] in tests.
+    ///
+    /// [`MemoryStorageApi`]: crate::MemoryStorageApi
+    pub fn with_client(client: S, public_key: String, secret_key: SecretKey) -> Self {
+        Self {
+            client,
+            public_key,
+            secret_key,
+        }
+    }
+
     // // TODO: For testing only, delete before production
     // pub async fn delete_contracts(&self) {
     //     let _res = self.client.delete_contracts(self.key.clone());
@@ -42,7 +70,7 @@ impl AsyncStorageApiProvider {
                 ContractsRequestParams {
                     state: Some(state),
                     key: self.public_key.clone(),
-                    uuid: None,
+                    ..Default::default()
                 },
                 self.secret_key,
             )
@@ -63,7 +91,7 @@ impl AsyncStorageApiProvider {
     }
 }
 
-impl AsyncStorage for AsyncStorageApiProvider {
+impl<S: StorageApi> AsyncStorage for AsyncStorageApiProvider<S> {
     async fn get_contract(&self, id: &ContractId) -> Result<Option<DlcContract>, Error> {
         let cid = get_contract_id_string(*id);
         let contract_res = self
@@ -93,8 +121,7 @@ impl AsyncStorage for AsyncStorageApiProvider {
             .get_contracts(
                 ContractsRequestParams {
                     key: self.public_key.clone(),
-                    uuid: None,
-                    state: None,
+                    ..Default::default()
                 },
                 self.secret_key,
             )
@@ -114,13 +141,15 @@ impl AsyncStorage for AsyncStorageApiProvider {
     }
 
     async fn create_contract(&self, contract: &OfferedContract) -> Result<(), Error> {
-        let data = serialize_contract(&DlcContract::Offered(contract.clone()))?;
         let uuid = get_contract_id_string(contract.id);
+        let contract = DlcContract::Offered(contract.clone());
+        let data = serialize_contract(&contract)?;
         let req = NewContract {
             uuid: uuid.clone(),
             state: "offered".to_string(),
             content: base64::encode(&data),
             key: self.public_key.clone(),
+            metadata: get_contract_metadata(&contract),
         };
         self.client
             .create_contract(req, self.secret_key)
@@ -156,13 +185,15 @@ impl AsyncStorage for AsyncStorageApiProvider {
                             state: Some(get_contract_state_str(contract)),
                             content: Some(base64::encode(serialize_contract(contract)?)),
                             key: self.public_key.clone(),
+                            metadata: get_contract_metadata(contract),
                         },
                         self.secret_key,
                     )
                     .await
                 {
                     Ok(_) => {}
-                    Err(_) => {
+                    // stored under its temporary id until now
+                    Err(ApiError::NotFound(_)) => {
                         self.client
                             .create_contract(
                                 NewContract {
@@ -170,12 +201,14 @@ impl AsyncStorage for AsyncStorageApiProvider {
                                     state: get_contract_state_str(contract),
                                     content: base64::encode(serialize_contract(contract)?),
                                     key: self.public_key.clone(),
+                                    metadata: get_contract_metadata(contract),
                                 },
                                 self.secret_key,
                             )
                             .await
                             .map_err(to_storage_error)?;
                     }
+                    Err(e) => return Err(to_storage_error(e)),
                 }
                 Ok(())
             }
@@ -187,6 +220,7 @@ impl AsyncStorage for AsyncStorageApiProvider {
                             state: Some(get_contract_state_str(contract)),
                             content: Some(base64::encode(serialize_contract(contract)?)),
                             key: self.public_key.clone(),
+                            metadata: get_contract_metadata(contract),
                         },
                         self.secret_key,
                     )
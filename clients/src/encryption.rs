@@ -0,0 +1,167 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use secp256k1_zkp::hashes::{sha256, Hash, HashEngine};
+use secp256k1_zkp::SecretKey;
+
+/// Marks content encrypted with the version 1 scheme. Content without a known prefix
+/// was stored before encryption existed and is returned as is.
+const V1_PREFIX: &str = "enc1:";
+const KEY_DERIVATION_TAG: &[u8] = b"dlc-storage-api/content-encryption/v1";
+const NONCE_LEN: usize = 12;
+
+/// The contract or event a content is stored in.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Row<'a> {
+    Contract { key: &'a str, uuid: &'a str },
+    Event { key: &'a str, event_id: &'a str },
+}
+
+impl Row<'_> {
+    /// The associated data content of this row is encrypted with, each part prefixed by
+    /// its length so that different keys and ids can't run into each other.
+    fn aad(&self) -> Vec<u8> {
+        let (entity, key, id) = match self {
+            Row::Contract { key, uuid } => ("contract", key, uuid),
+            Row::Event { key, event_id } => ("event", key, event_id),
+        };
+        let mut aad = vec![];
+        for part in [entity, key, id] {
+            aad.extend((part.len() as u64).to_be_bytes());
+            aad.extend(part.as_bytes());
+        }
+        aad
+    }
+}
+
+/// Encrypts the content of contracts and events before it leaves the client.
+///
+/// Version 1 is ChaCha20-Poly1305 with a random nonce, under a key derived from the
+/// secret key the storage API requests are signed with, and with the [`Row`] as
+/// associated data, so the storage API can't hand out one row's content as another's.
+/// The stored form is `enc1:` followed by the base64 of nonce and ciphertext.
+pub(crate) struct ContentCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl ContentCipher {
+    pub fn new(secret_key: &SecretKey) -> Self {
+        let mut engine = sha256::Hash::engine();
+        engine.input(KEY_DERIVATION_TAG);
+        engine.input(&secret_key.secret_bytes());
+        let key = sha256::Hash::from_engine(engine);
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key.as_ref())),
+        }
+    }
+
+    pub fn encrypt(&self, row: Row, content: &str) -> Result<String, String> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: content.as_bytes(),
+            aad: &row.aad(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| "failed to encrypt content".to_string())?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!("{}{}", V1_PREFIX, base64::encode(sealed)))
+    }
+
+    pub fn decrypt(&self, row: Row, content: String) -> Result<String, String> {
+        let sealed = match content.strip_prefix(V1_PREFIX) {
+            Some(sealed) => base64::decode(sealed)
+                .map_err(|e| format!("encrypted content is not base64: {}", e))?,
+            None => return Ok(content),
+        };
+        if sealed.len() < NONCE_LEN {
+            return Err("encrypted content is too short".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &row.aad(),
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| {
+                "failed to decrypt content, it was encrypted with another key or for another row"
+                    .to_string()
+            })?;
+        String::from_utf8(plaintext).map_err(|e| format!("decrypted content is not utf-8: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret_key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).expect("valid secret key")
+    }
+
+    const ROW: Row = Row::Contract {
+        key: "k1",
+        uuid: "c1",
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let cipher = ContentCipher::new(&secret_key(1));
+        let encrypted = cipher.encrypt(ROW, "c29tZSBjb250ZW50").expect("encrypts");
+        assert!(encrypted.starts_with(V1_PREFIX));
+        assert!(!encrypted.contains("c29tZSBjb250ZW50"));
+        // a fresh nonce every time
+        assert_ne!(
+            encrypted,
+            cipher.encrypt(ROW, "c29tZSBjb250ZW50").expect("encrypts")
+        );
+        assert_eq!(
+            cipher.decrypt(ROW, encrypted).expect("decrypts"),
+            "c29tZSBjb250ZW50"
+        );
+    }
+
+    #[test]
+    fn test_legacy_content_passes_through() {
+        let cipher = ContentCipher::new(&secret_key(1));
+        assert_eq!(
+            cipher.decrypt(ROW, "c29tZSBjb250ZW50".to_string()),
+            Ok("c29tZSBjb250ZW50".to_string())
+        );
+    }
+
+    #[test]
+    fn test_wrong_key_fails() {
+        let encrypted = ContentCipher::new(&secret_key(1))
+            .encrypt(ROW, "c29tZSBjb250ZW50")
+            .expect("encrypts");
+        assert!(ContentCipher::new(&secret_key(2))
+            .decrypt(ROW, encrypted)
+            .is_err());
+    }
+
+    #[test]
+    fn test_swapped_content_fails() {
+        let cipher = ContentCipher::new(&secret_key(1));
+        let encrypted = cipher.encrypt(ROW, "c29tZSBjb250ZW50").expect("encrypts");
+        for row in [
+            Row::Contract {
+                key: "k1",
+                uuid: "c2",
+            },
+            Row::Contract {
+                key: "k2",
+                uuid: "c1",
+            },
+            Row::Event {
+                key: "k1",
+                event_id: "c1",
+            },
+        ] {
+            assert!(cipher.decrypt(row, encrypted.clone()).is_err(), "{:?}", row);
+        }
+    }
+}
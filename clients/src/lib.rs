@@ -2,20 +2,105 @@
 #![deny(clippy::unwrap_used)]
 extern crate serde;
 
+use encryption::{ContentCipher, Row};
+use futures_util::stream::{self, Stream, StreamExt};
 use log::{debug, error};
-use reqwest::{Client, Response};
+use reqwest::{Client, Method, Response, StatusCode};
 use secp256k1_zkp::hashes::{sha256, Hash};
-use secp256k1_zkp::{ecdsa, Message, Secp256k1, SecretKey};
+use secp256k1_zkp::{KeyPair, Message, Secp256k1, SecretKey};
 
+use retry::{CircuitBreaker, SendError};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{error, fmt};
 
 pub mod async_storage_provider;
-mod utils;
+mod encryption;
+pub mod memory;
+mod retry;
+mod sse;
+mod storage_api;
+pub mod utils;
+
+pub use memory::MemoryStorageApi;
+pub use retry::RetryPolicy;
+pub use storage_api::StorageApi;
 
 const REQWEST_TIMEOUT: Duration = Duration::from_secs(30);
+const SIGNATURE_SCHEME_HEADER: &str = "x-signature-scheme";
+/// How long requests are signed instead of logging in again after a failed login,
+/// doubled for every failure in a row up to `MAX_LOGIN_BACKOFF`.
+const LOGIN_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_LOGIN_BACKOFF: Duration = Duration::from_secs(600);
+
+/// How requests to the storage API are signed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// ECDSA by the compressed public key.
+    #[default]
+    Ecdsa,
+    /// BIP340 Schnorr by the x-only public key, which is then the `key` requests
+    /// have to use.
+    Schnorr,
+}
+
+impl SignatureScheme {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SignatureScheme::Ecdsa => "ecdsa",
+            SignatureScheme::Schnorr => "schnorr",
+        }
+    }
+
+    /// The public key of `secret_key`, encoded the way requests signed with it name
+    /// their `key`.
+    fn public_key(&self, secret_key: SecretKey) -> String {
+        let signer = Secp256k1::signing_only();
+        match self {
+            SignatureScheme::Ecdsa => secret_key.public_key(&signer).to_string(),
+            SignatureScheme::Schnorr => KeyPair::from_secret_key(&signer, &secret_key)
+                .x_only_public_key()
+                .0
+                .to_string(),
+        }
+    }
+}
+
+/// What the storage API expects a request's signature to cover: method, path and
+/// nonce, and the canonical query of a GET or the signed message of anything else.
+fn signed_payload(method: &str, path: &str, nonce: &str, payload: &str) -> String {
+    format!("{}\n{}\n{}\n{}", method, path, nonce, payload)
+}
+
+/// The JSON body of a successful response, or the error the storage API answered with.
+/// `context` says what went wrong when the body is not what was expected.
+async fn read_json<T: serde::de::DeserializeOwned>(
+    res: Response,
+    context: &str,
+) -> Result<T, ApiError> {
+    let status = res.status();
+    if !status.is_success() {
+        return Err(ApiError::from_response(
+            status.as_u16(),
+            &res.text().await.unwrap_or_default(),
+        ));
+    }
+    res.json::<T>()
+        .await
+        .map_err(|e| ApiError::Decode(format!("{}, error: {}", context, e)))
+}
+
+/// The query without its signature, with the parameters sorted by name.
+fn canonical_query(query: &str) -> String {
+    let mut params: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap_or_default();
+    params.retain(|(name, _)| name != "signature");
+    params.sort();
+    serde_urlencoded::to_string(params).unwrap_or_default()
+}
 
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -45,28 +130,80 @@ pub struct ApiResult {
     pub response: Response,
 }
 
-#[derive(Debug, Clone)]
-pub struct ApiError {
-    pub message: String,
-    pub status: u16,
+/// Why a request to the storage API failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    /// There is no such contract, event or key, or it is deleted.
+    NotFound(String),
+    /// A contract or event with that uuid or event id already exists.
+    Conflict(String),
+    /// The signature, nonce or session was rejected, or the key may not do this.
+    Unauthorized(String),
+    /// Too many requests or writes, retry later.
+    RateLimited(String),
+    /// The storage API couldn't be reached or didn't answer in time.
+    Transport(String),
+    /// A request couldn't be encoded, or a response or its content couldn't be decoded
+    /// or decrypted.
+    Decode(String),
+    /// Any other error status, e.g. a 400 for an invalid request or a 5xx.
+    Server { status: u16, message: String },
+}
+
+impl ApiError {
+    /// The error for an error response with `status` and `body`, which carries the
+    /// storage API's message if it is its JSON error body.
+    pub fn from_response(status: u16, body: &str) -> Self {
+        let message = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|body| body["error"]["message"].as_str().map(str::to_string))
+            .unwrap_or_else(|| body.to_string());
+        match status {
+            404 => ApiError::NotFound(message),
+            409 => ApiError::Conflict(message),
+            401 | 403 => ApiError::Unauthorized(message),
+            429 => ApiError::RateLimited(message),
+            status => ApiError::Server { status, message },
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::RateLimited(message)
+            | ApiError::Transport(message)
+            | ApiError::Decode(message)
+            | ApiError::Server { message, .. } => message,
+        }
+    }
 }
 
-// implement from reqwest error trait for ApiError
 impl From<reqwest::Error> for ApiError {
     fn from(e: reqwest::Error) -> Self {
-        ApiError {
-            message: e.to_string(),
-            status: e
-                .status()
-                .unwrap_or(reqwest::StatusCode::BAD_REQUEST)
-                .into(),
+        if e.is_decode() {
+            return ApiError::Decode(e.to_string());
+        }
+        match e.status() {
+            Some(status) => ApiError::from_response(status.as_u16(), &e.to_string()),
+            None => ApiError::Transport(e.to_string()),
         }
     }
 }
 
 impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ApiError: {} - {}", self.status, self.message)
+        let kind = match self {
+            ApiError::NotFound(_) => "not found".to_string(),
+            ApiError::Conflict(_) => "conflict".to_string(),
+            ApiError::Unauthorized(_) => "unauthorized".to_string(),
+            ApiError::RateLimited(_) => "rate limited".to_string(),
+            ApiError::Transport(_) => "transport error".to_string(),
+            ApiError::Decode(_) => "decode error".to_string(),
+            ApiError::Server { status, .. } => status.to_string(),
+        };
+        write!(f, "ApiError: {} - {}", kind, self.message())
     }
 }
 
@@ -76,12 +213,36 @@ impl error::Error for ApiError {
     }
 }
 
+/// Metadata stored in the clear next to a contract's content, so contracts can be
+/// filtered by it. `collateral` is the total collateral in sats. Fields left `None` in
+/// an update keep their stored value.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ContractMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oracle_event_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub funding_txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collateral: Option<i64>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct Contract {
     pub id: i32,
     pub uuid: String,
     pub state: String,
     pub content: String,
+    pub key: String,
+    #[serde(flatten)]
+    pub metadata: ContractMetadata,
+    /// When the contract was soft deleted, as a unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    /// When the contract was last written, as a unix timestamp.
+    #[serde(default)]
+    pub updated_at: Option<i64>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -90,6 +251,8 @@ pub struct NewContract {
     pub state: String,
     pub content: String,
     pub key: String,
+    #[serde(flatten)]
+    pub metadata: ContractMetadata,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -98,6 +261,8 @@ pub struct UpdateContract {
     pub state: Option<String>,
     pub content: Option<String>,
     pub key: String,
+    #[serde(flatten)]
+    pub metadata: ContractMetadata,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -106,14 +271,24 @@ pub struct ContractRequestParams {
     pub uuid: String,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+/// Filters of [`StorageApiClient::get_contracts`]. Metadata filters only match contracts
+/// that have the field set; the collateral bounds are inclusive.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
 pub struct ContractsRequestParams {
     pub key: String,
     pub uuid: Option<String>,
+    /// One state, or several separated by commas, e.g. `signed,confirmed`.
     pub state: Option<String>,
+    pub oracle_event_id: Option<String>,
+    pub funding_txid: Option<String>,
+    pub counterparty: Option<String>,
+    pub collateral_min: Option<i64>,
+    pub collateral_max: Option<i64>,
+    /// Also return soft deleted contracts.
+    pub include_deleted: Option<bool>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
 pub struct SignedContractsRequestParams {
     key: String,
     #[serde(
@@ -128,7 +303,59 @@ pub struct SignedContractsRequestParams {
         with = "::serde_with::rust::unwrap_or_skip"
     )]
     state: Option<String>,
-    signature: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    oracle_event_id: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    funding_txid: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    counterparty: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    collateral_min: Option<i64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    collateral_max: Option<i64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    include_deleted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+/// Metadata stored in the clear next to an event's content, so events can be filtered
+/// by it. `maturity` is a unix timestamp. Fields left `None` in an update keep their
+/// stored value.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EventMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maturity: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attested: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -136,14 +363,21 @@ pub struct NewEvent {
     pub event_id: String,
     pub content: String,
     pub key: String,
+    #[serde(flatten)]
+    pub metadata: EventMetadata,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct Event {
-    pub id: usize,
+    pub id: i32,
     pub event_id: String,
     pub content: String,
     pub key: String,
+    #[serde(flatten)]
+    pub metadata: EventMetadata,
+    /// When the event was soft deleted, as a unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -151,6 +385,8 @@ pub struct UpdateEvent {
     pub event_id: String,
     pub content: String,
     pub key: String,
+    #[serde(flatten)]
+    pub metadata: EventMetadata,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -162,7 +398,44 @@ struct SignedEventsRequestParams {
         with = "::serde_with::rust::unwrap_or_skip"
     )]
     event_id: Option<String>,
-    signature: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    attested: Option<bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    outcome: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    chain: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    matures_before: Option<i64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    matures_after: Option<i64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    include_deleted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -171,21 +444,290 @@ pub struct EventRequestParams {
     pub event_id: String,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+/// Filters of [`StorageApiClient::get_events`]. Metadata filters only match events that
+/// have the field set; the maturity bounds are exclusive unix timestamps.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
 pub struct EventsRequestParams {
     pub key: String,
     pub event_id: Option<String>,
+    pub attested: Option<bool>,
+    pub outcome: Option<String>,
+    pub chain: Option<String>,
+    pub matures_before: Option<i64>,
+    pub matures_after: Option<i64>,
+    /// Also return soft deleted events.
+    pub include_deleted: Option<bool>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeEntity {
+    Contract,
+    Event,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// A change to one of a key's contracts or events, as sent by the change feed.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Cursor to resume the feed from.
+    pub id: i32,
+    pub key: String,
+    pub entity: ChangeEntity,
+    /// The contract uuid or the event id.
+    pub entity_id: String,
+    pub kind: ChangeKind,
+    /// The new contract state, if it changed.
+    pub state: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct SignedChangesRequestParams {
+    key: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    after: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+/// Everything stored for a key, as exported by the storage API.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Archive {
+    pub version: u32,
+    pub key: String,
+    /// When the archive was exported, as a unix timestamp.
+    pub exported_at: i64,
+    /// Picked by the storage API, an archive can only be imported once.
+    pub nonce: String,
+    pub contracts: Vec<ArchivedContract>,
+    pub events: Vec<ArchivedEvent>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchivedContract {
+    pub uuid: String,
+    pub state: String,
+    pub content: String,
+    #[serde(flatten)]
+    pub metadata: ContractMetadata,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchivedEvent {
+    pub event_id: String,
+    pub content: String,
+    #[serde(flatten)]
+    pub metadata: EventMetadata,
+}
+
+/// An archive signed by its key, ready to be stored and later imported.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SignedArchive {
+    pub archive: Archive,
+    pub signature: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+/// A write of a [`Batch`].
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    CreateContract(NewContract),
+    UpdateContract(UpdateContract),
+    DeleteContract(ContractRequestParams),
+    CreateEvent(NewEvent),
+    UpdateEvent(UpdateEvent),
+    DeleteEvent(EventRequestParams),
+}
+
+/// Contract and event writes of one key, sent with one signature and applied in one
+/// transaction by [`StorageApiClient::apply_batch`].
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Batch {
+    key: String,
+    operations: Vec<BatchOperation>,
+}
+
+impl Batch {
+    /// An empty batch for `key`, which every operation has to be for.
+    pub fn new(key: String) -> Self {
+        Self {
+            key,
+            operations: vec![],
+        }
+    }
+
+    pub fn create_contract(mut self, contract: NewContract) -> Self {
+        self.operations
+            .push(BatchOperation::CreateContract(contract));
+        self
+    }
+
+    pub fn update_contract(mut self, contract: UpdateContract) -> Self {
+        self.operations
+            .push(BatchOperation::UpdateContract(contract));
+        self
+    }
+
+    pub fn delete_contract(mut self, contract: ContractRequestParams) -> Self {
+        self.operations
+            .push(BatchOperation::DeleteContract(contract));
+        self
+    }
+
+    pub fn create_event(mut self, event: NewEvent) -> Self {
+        self.operations.push(BatchOperation::CreateEvent(event));
+        self
+    }
+
+    pub fn update_event(mut self, event: UpdateEvent) -> Self {
+        self.operations.push(BatchOperation::UpdateEvent(event));
+        self
+    }
+
+    pub fn delete_event(mut self, event: EventRequestParams) -> Self {
+        self.operations.push(BatchOperation::DeleteEvent(event));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+/// What an operation of a batch returned: the created contract or event, or how many
+/// rows an update or delete touched.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Contract(Contract),
+    Event(Event),
+    Effected { effected_num: usize },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+/// What a key registered with the storage API may write.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Writes events.
+    Attestor,
+    /// Writes contracts, also in batches, imports and bulk deletes.
+    RouterWallet,
+    /// Writes contracts one at a time, for end users.
+    Wallet,
+}
+
+/// A key the storage API serves when it requires registration.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct RegisteredKey {
+    pub key: String,
+    pub role: Role,
+    pub created_at: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct RegisterKey {
+    key: String,
+    public_key: String,
+    role: Role,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct UnregisterKey {
+    key: String,
+    public_key: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct SignedExportRequestParams {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct LoginResponse {
+    token: String,
+}
+
+/// Session tokens by the public key they were issued for.
+#[derive(Default)]
+struct Sessions {
+    tokens: HashMap<String, String>,
+    // the storage API predates sessions
+    unsupported: bool,
+    // failed logins in a row, and when to try logging in again
+    login_failures: u32,
+    retry_login_at: Option<Duration>,
+}
+
+impl Sessions {
+    /// Whether to log in, unless the storage API has no sessions or a login failed
+    /// recently.
+    fn may_login(&self) -> bool {
+        !self.unsupported && self.retry_login_at.map_or(true, |at| retry::now() >= at)
+    }
+
+    fn logged_in(&mut self, key: String, token: String) {
+        self.login_failures = 0;
+        self.retry_login_at = None;
+        self.tokens.insert(key, token);
+    }
+
+    /// Signs requests instead of logging in for a while, so an outage of the storage
+    /// API doesn't cost a login attempt per request.
+    fn login_failed(&mut self) {
+        let backoff = LOGIN_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.login_failures))
+            .min(MAX_LOGIN_BACKOFF);
+        self.login_failures = self.login_failures.saturating_add(1);
+        self.retry_login_at = Some(retry::now() + backoff);
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 struct EffectedNumResponse {
-    pub effected_num: u32,
+    pub effected_num: usize,
 }
 
 #[derive(Clone)]
 pub struct StorageApiClient {
     client: Client,
+    // without the request timeout, for the long lived change feed
+    stream_client: Client,
     host: String,
+    encrypt_content: bool,
+    plaintext_metadata: bool,
+    signature_scheme: SignatureScheme,
+    sessions: Arc<Mutex<Sessions>>,
+    retry_policy: RetryPolicy,
+    breaker: Arc<Mutex<CircuitBreaker>>,
 }
 
 impl Default for StorageApiClient {
@@ -202,101 +744,418 @@ impl Debug for StorageApiClient {
 
 impl StorageApiClient {
     pub fn new(host: String) -> Self {
-        let mut client_builder = Client::builder();
+        let mut stream_client_builder = Client::builder();
         #[cfg(not(target_arch = "wasm32"))]
         {
-            client_builder = client_builder.tcp_keepalive(Some(Duration::from_secs(20)));
-            client_builder = client_builder.timeout(REQWEST_TIMEOUT);
+            stream_client_builder =
+                stream_client_builder.tcp_keepalive(Some(Duration::from_secs(20)));
+            stream_client_builder = stream_client_builder.connect_timeout(REQWEST_TIMEOUT);
         }
+        let retry_policy = RetryPolicy::default();
         Self {
-            client: client_builder
+            client: Self::build_client(retry_policy.timeout),
+            stream_client: stream_client_builder
                 .build()
                 .expect("Storage API Client should be able to create a reqwest client"),
             host,
+            encrypt_content: false,
+            plaintext_metadata: false,
+            signature_scheme: SignatureScheme::default(),
+            sessions: Arc::new(Mutex::new(Sessions::default())),
+            retry_policy,
+            breaker: Arc::new(Mutex::new(CircuitBreaker::default())),
+        }
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+    fn build_client(timeout: Duration) -> Client {
+        let mut client_builder = Client::builder();
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            client_builder = client_builder.tcp_keepalive(Some(Duration::from_secs(20)));
+            client_builder = client_builder.timeout(timeout);
+        }
+        client_builder
+            .build()
+            .expect("Storage API Client should be able to create a reqwest client")
+    }
+
+    /// Retries and times out requests by `policy` instead of [`RetryPolicy::default`].
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.client = Self::build_client(policy.timeout);
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Signs requests with `scheme` instead of ECDSA.
+    pub fn with_signature_scheme(mut self, scheme: SignatureScheme) -> Self {
+        self.signature_scheme = scheme;
+        self
+    }
+
+    /// Encrypts the content of created and updated contracts and events with a key
+    /// derived from the secret key each request is signed with, so the storage API
+    /// only ever sees ciphertext.
+    ///
+    /// Encrypted content is decrypted on read whether or not this is set, and content
+    /// stored before encryption was turned on is still read as is.
+    ///
+    /// The metadata of contracts and events is not sent either then, since it is read
+    /// off the content, see [`Self::with_plaintext_metadata`].
+    pub fn with_content_encryption(mut self) -> Self {
+        self.encrypt_content = true;
+        self
+    }
+
+    /// Sends the metadata of contracts and events even while their content is encrypted,
+    /// so the storage API can filter by it. It is stored in plaintext: the counterparty,
+    /// funding txid, collateral and oracle event id of a contract, and the maturity,
+    /// attestation, outcome and chain of an event.
+    pub fn with_plaintext_metadata(mut self) -> Self {
+        self.plaintext_metadata = true;
+        self
+    }
+
+    /// The metadata to send along with content, none if the content is encrypted and the
+    /// metadata would give it away.
+    fn reveal<M: Default>(&self, metadata: M) -> M {
+        if self.encrypt_content && !self.plaintext_metadata {
+            return M::default();
         }
+        metadata
+    }
+
+    fn seal(&self, secret_key: &SecretKey, row: Row, content: String) -> Result<String, ApiError> {
+        if !self.encrypt_content {
+            return Ok(content);
+        }
+        ContentCipher::new(secret_key)
+            .encrypt(row, &content)
+            .map_err(ApiError::Decode)
+    }
+
+    fn open(&self, secret_key: &SecretKey, row: Row, content: String) -> Result<String, ApiError> {
+        ContentCipher::new(secret_key)
+            .decrypt(row, content)
+            .map_err(ApiError::Decode)
     }
 
     async fn build_signed_message(
         &self,
         secret_key: SecretKey,
+        method: &str,
+        path: &str,
         mut message: Value,
-    ) -> Result<(String, SignedMessage), ApiError> {
-        let nonce = self.request_nonce().await?;
+    ) -> Result<(String, SignedMessage), reqwest::Error> {
+        let nonce = self.nonce().await?;
         message["nonce"] = nonce.clone().into();
 
-        let (sig, pub_key) = self.sign(secret_key, message.to_string());
+        let payload = signed_payload(method, path, &nonce, &message.to_string());
+        let (sig, pub_key) = self.sign(secret_key, payload);
         let message_body = SignedMessage {
             message,
-            public_key: pub_key.to_string(),
-            signature: sig.to_string(),
+            public_key: pub_key,
+            signature: sig,
         };
         Ok((nonce, message_body))
     }
 
-    fn sign(
-        &self,
-        secret_key: SecretKey,
-        message: String,
-    ) -> (ecdsa::Signature, secp256k1_zkp::PublicKey) {
-        let signer = Secp256k1::new();
-        let public_key = secret_key.public_key(&signer);
-        let digest = Message::from(sha256::Hash::hash(message.as_bytes()));
-        (signer.sign_ecdsa(&digest, &secret_key), public_key)
+    /// A session token for the key of `secret_key`, from a login if there is none yet.
+    /// None if the storage API has no sessions or a login failed recently, the request
+    /// is then signed instead.
+    async fn session_token(&self, secret_key: SecretKey) -> Option<String> {
+        let key = self.public_key(secret_key);
+        {
+            let sessions = self.sessions.lock().expect("Failed to lock sessions");
+            if let Some(token) = sessions.tokens.get(&key) {
+                return Some(token.clone());
+            }
+            if !sessions.may_login() {
+                return None;
+            }
+        }
+        let login = self.login(secret_key).await;
+        let mut sessions = self.sessions.lock().expect("Failed to lock sessions");
+        match login {
+            Ok(token) => {
+                sessions.logged_in(key, token.clone());
+                Some(token)
+            }
+            Err(e) => {
+                debug!("login failed, signing requests instead: {}", e);
+                match e {
+                    ApiError::NotFound(_) => sessions.unsupported = true,
+                    _ => sessions.login_failed(),
+                }
+                None
+            }
+        }
     }
 
-    pub async fn request_nonce(&self) -> Result<String, ApiError> {
-        let uri = format!("{}/request_nonce", String::as_str(&self.host.clone()));
-        let res = self.client.get(uri).send().await?;
-        let nonce = res.text().await?;
-        Ok(nonce)
+    fn forget_session(&self, secret_key: SecretKey) {
+        let key = self.public_key(secret_key);
+        self.sessions
+            .lock()
+            .expect("Failed to lock sessions")
+            .tokens
+            .remove(&key);
     }
 
-    pub async fn get_contracts(
-        &self,
-        contract_req: ContractsRequestParams,
-        secret_key: SecretKey,
-    ) -> Result<Vec<Contract>, ApiError> {
-        let uri = format!("{}/contracts", String::as_str(&self.host.clone()),);
-
-        let nonce = self.request_nonce().await?;
-        let (sig, _pubkey) = self.sign(secret_key, nonce.clone());
-        let signed_request_params = SignedContractsRequestParams {
-            key: contract_req.key.clone(),
-            uuid: contract_req.uuid.clone(),
-            state: contract_req.state.clone(),
-            signature: sig.to_string(),
-        };
-
+    async fn login(&self, secret_key: SecretKey) -> Result<String, ApiError> {
+        let uri = format!("{}/login", String::as_str(&self.host.clone()));
+        let (nonce, message_body) = self
+            .build_signed_message(secret_key, "POST", "/login", json!({}))
+            .await?;
         let res = self
             .client
-            .get(uri)
+            .post(uri)
             .header("authorization", nonce)
-            .query(&json!(signed_request_params))
+            .header(SIGNATURE_SCHEME_HEADER, self.signature_scheme.as_str())
+            .json(&message_body)
             .send()
             .await?;
-        let status = res.status().into();
-        let contracts = res.json::<Vec<Contract>>().await.map_err(|e| ApiError {
-            message: format!(
-                "get contracts failed, response from API not a list of contract objects, error: {}",
-                e
-            ),
-            status,
-        })?;
-        Ok(contracts)
+        let session =
+            read_json::<LoginResponse>(res, "login failed, response from API not a session")
+                .await?;
+        Ok(session.token)
     }
 
-    pub async fn get_contract(
+    /// Sends the request `attempt` makes unless the circuit breaker is open, and if it
+    /// is `idempotent` tries it again while it fails transiently, by the retry policy.
+    async fn send_with_retries<F, Fut>(
         &self,
-        contract_req: ContractRequestParams,
-        secret_key: SecretKey,
-    ) -> Result<Option<Contract>, ApiError> {
-        debug!("getting contract with uuid: {}", contract_req.uuid);
-        let contract = self
-            .get_contracts(
+        idempotent: bool,
+        attempt: F,
+    ) -> Result<Response, ApiError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Response, SendError>>,
+    {
+        if !self
+            .breaker
+            .lock()
+            .expect("Failed to lock circuit breaker")
+            .allows()
+        {
+            return Err(retry::circuit_open());
+        }
+        let mut retries = 0;
+        loop {
+            let outcome = attempt().await;
+            let transient = retry::is_transient(&outcome);
+            if transient && idempotent && retries < self.retry_policy.max_retries {
+                let backoff = self.retry_policy.backoff(retries);
+                debug!("request failed transiently, retrying in {:?}", backoff);
+                retry::sleep(backoff).await;
+                retries += 1;
+                continue;
+            }
+            self.breaker
+                .lock()
+                .expect("Failed to lock circuit breaker")
+                .record(&self.retry_policy, transient);
+            return Ok(outcome?);
+        }
+    }
+
+    /// Sends a GET to `path` with the query `params`, authenticated by the session token
+    /// of `secret_key`'s key, or by a signature when there is no (valid) token.
+    async fn send_signed_get(
+        &self,
+        client: &Client,
+        path: &str,
+        params: Value,
+        secret_key: SecretKey,
+    ) -> Result<Response, ApiError> {
+        self.send_with_retries(true, || {
+            self.try_send_signed_get(client, path, params.clone(), secret_key)
+        })
+        .await
+    }
+
+    async fn try_send_signed_get(
+        &self,
+        client: &Client,
+        path: &str,
+        mut params: Value,
+        secret_key: SecretKey,
+    ) -> Result<Response, SendError> {
+        let uri = format!("{}{}", self.host, path);
+        if let Some(token) = self.session_token(secret_key).await {
+            let res = client
+                .get(&uri)
+                .header("authorization", format!("Bearer {}", token))
+                .query(&params)
+                .send()
+                .await?;
+            if res.status() != StatusCode::UNAUTHORIZED {
+                return Ok(res);
+            }
+            // expired, the next request logs in again
+            self.forget_session(secret_key);
+        }
+        let nonce = self.nonce().await?;
+        params["signature"] = self.sign_query(secret_key, path, &nonce, &params)?.into();
+        Ok(client
+            .get(uri)
+            .header("authorization", nonce)
+            .header(SIGNATURE_SCHEME_HEADER, self.signature_scheme.as_str())
+            .query(&params)
+            .send()
+            .await?)
+    }
+
+    /// Sends `message` to `path`, like [`Self::send_signed_get`] either with the session
+    /// token or as a signed message. Only updates are retried.
+    async fn send_signed(
+        &self,
+        method: Method,
+        path: &str,
+        message: Value,
+        secret_key: SecretKey,
+    ) -> Result<Response, ApiError> {
+        self.send_with_retries(method == Method::PUT, || {
+            self.try_send_signed(method.clone(), path, message.clone(), secret_key)
+        })
+        .await
+    }
+
+    async fn try_send_signed(
+        &self,
+        method: Method,
+        path: &str,
+        message: Value,
+        secret_key: SecretKey,
+    ) -> Result<Response, SendError> {
+        let uri = format!("{}{}", self.host, path);
+        if let Some(token) = self.session_token(secret_key).await {
+            let res = self
+                .client
+                .request(method.clone(), &uri)
+                .header("authorization", format!("Bearer {}", token))
+                .json(&message)
+                .send()
+                .await?;
+            if res.status() != StatusCode::UNAUTHORIZED {
+                return Ok(res);
+            }
+            self.forget_session(secret_key);
+        }
+        let (nonce, message_body) = self
+            .build_signed_message(secret_key, method.as_str(), path, message)
+            .await?;
+        Ok(self
+            .client
+            .request(method, uri)
+            .header("authorization", nonce)
+            .header(SIGNATURE_SCHEME_HEADER, self.signature_scheme.as_str())
+            .json(&message_body)
+            .send()
+            .await?)
+    }
+
+    /// Signs a GET to `path` with the query `params`, which have to serialize to the
+    /// query that is sent, apart from the signature.
+    fn sign_query<T: serde::Serialize>(
+        &self,
+        secret_key: SecretKey,
+        path: &str,
+        nonce: &str,
+        params: &T,
+    ) -> Result<String, ApiError> {
+        let query = serde_urlencoded::to_string(params)
+            .map_err(|e| ApiError::Decode(format!("failed to encode query: {}", e)))?;
+        let payload = signed_payload("GET", path, nonce, &canonical_query(&query));
+        Ok(self.sign(secret_key, payload).0)
+    }
+
+    /// Signs the sha256 of `message`, returns the signature and the public key that
+    /// verifies it, both encoded for the signature scheme.
+    fn sign(&self, secret_key: SecretKey, message: String) -> (String, String) {
+        let signer = Secp256k1::new();
+        let digest = Message::from(sha256::Hash::hash(message.as_bytes()));
+        let signature = match self.signature_scheme {
+            SignatureScheme::Ecdsa => signer.sign_ecdsa(&digest, &secret_key).to_string(),
+            SignatureScheme::Schnorr => {
+                let key_pair = KeyPair::from_secret_key(&signer, &secret_key);
+                signer
+                    .sign_schnorr_no_aux_rand(&digest, &key_pair)
+                    .to_string()
+            }
+        };
+        (signature, self.public_key(secret_key))
+    }
+
+    /// The public key of `secret_key`, encoded for the signature scheme.
+    fn public_key(&self, secret_key: SecretKey) -> String {
+        self.signature_scheme.public_key(secret_key)
+    }
+
+    pub async fn request_nonce(&self) -> Result<String, ApiError> {
+        Ok(self.nonce().await?)
+    }
+
+    async fn nonce(&self) -> Result<String, reqwest::Error> {
+        let uri = format!("{}/request_nonce", String::as_str(&self.host.clone()));
+        let res = self.client.get(uri).send().await?;
+        res.text().await
+    }
+
+    pub async fn get_contracts(
+        &self,
+        contract_req: ContractsRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Vec<Contract>, ApiError> {
+        let request_params = SignedContractsRequestParams {
+            key: contract_req.key,
+            uuid: contract_req.uuid,
+            state: contract_req.state,
+            oracle_event_id: contract_req.oracle_event_id,
+            funding_txid: contract_req.funding_txid,
+            counterparty: contract_req.counterparty,
+            collateral_min: contract_req.collateral_min,
+            collateral_max: contract_req.collateral_max,
+            include_deleted: contract_req.include_deleted,
+            signature: None,
+        };
+        let res = self
+            .send_signed_get(
+                &self.client,
+                "/contracts",
+                json!(request_params),
+                secret_key,
+            )
+            .await?;
+        let mut contracts = read_json::<Vec<Contract>>(
+            res,
+            "get contracts failed, response from API not a list of contract objects",
+        )
+        .await?;
+        for contract in contracts.iter_mut() {
+            contract.content = self.open(
+                &secret_key,
+                Row::Contract {
+                    key: &contract.key,
+                    uuid: &contract.uuid,
+                },
+                std::mem::take(&mut contract.content),
+            )?;
+        }
+        Ok(contracts)
+    }
+
+    pub async fn get_contract(
+        &self,
+        contract_req: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Option<Contract>, ApiError> {
+        debug!("getting contract with uuid: {}", contract_req.uuid);
+        let contract = self
+            .get_contracts(
                 ContractsRequestParams {
                     uuid: Some(contract_req.uuid.clone()),
                     key: contract_req.key,
-                    state: None,
+                    ..Default::default()
                 },
                 secret_key,
             )
@@ -309,32 +1168,37 @@ impl StorageApiClient {
         event_req: EventsRequestParams,
         secret_key: SecretKey,
     ) -> Result<Vec<Event>, ApiError> {
-        let uri = format!("{}/events", String::as_str(&self.host.clone()));
         debug!("getting events with request params: {:?}", event_req);
 
-        let nonce = self.request_nonce().await?;
-        let (sig, _pubkey) = self.sign(secret_key, nonce.clone());
-        let signed_request_params = SignedEventsRequestParams {
-            key: event_req.key.clone(),
-            event_id: event_req.event_id.clone(),
-            signature: sig.to_string(),
+        let request_params = SignedEventsRequestParams {
+            key: event_req.key,
+            event_id: event_req.event_id,
+            attested: event_req.attested,
+            outcome: event_req.outcome,
+            chain: event_req.chain,
+            matures_before: event_req.matures_before,
+            matures_after: event_req.matures_after,
+            include_deleted: event_req.include_deleted,
+            signature: None,
         };
-
         let res = self
-            .client
-            .get(uri)
-            .header("authorization", nonce)
-            .query(&signed_request_params)
-            .send()
+            .send_signed_get(&self.client, "/events", json!(request_params), secret_key)
             .await?;
-        let status = res.status().into();
-        let events = res.json::<Vec<Event>>().await.map_err(|e| ApiError {
-            message: format!(
-                "get events failed, response from API not a list of event objects, error: {}",
-                e
-            ),
-            status,
-        })?;
+        let mut events = read_json::<Vec<Event>>(
+            res,
+            "get events failed, response from API not a list of event objects",
+        )
+        .await?;
+        for event in events.iter_mut() {
+            event.content = self.open(
+                &secret_key,
+                Row::Event {
+                    key: &event.key,
+                    event_id: &event.event_id,
+                },
+                std::mem::take(&mut event.content),
+            )?;
+        }
         Ok(events)
     }
 
@@ -349,6 +1213,7 @@ impl StorageApiClient {
                 EventsRequestParams {
                     key: event_req.key.clone(),
                     event_id: Some(event_req.event_id.clone()),
+                    ..Default::default()
                 },
                 secret_key,
             )
@@ -356,99 +1221,312 @@ impl StorageApiClient {
         Ok(events.first().cloned())
     }
 
-    pub async fn create_contract(
+    /// Subscribes to the changes of `key`'s contracts and events after the cursor `after`,
+    /// from the start of the key's history if `None`.
+    ///
+    /// The stream ends when the connection drops. Subscribe again with the `id` of the
+    /// last change received to resume without missing any.
+    pub async fn subscribe_changes(
         &self,
-        contract: NewContract,
+        key: String,
+        after: Option<i32>,
         secret_key: SecretKey,
-    ) -> Result<Contract, ApiError> {
-        let uri: String = format!("{}/contracts", String::as_str(&self.host.clone()));
-        debug!("calling contract create on url: {:?}", uri);
+    ) -> Result<impl Stream<Item = Result<Change, ApiError>>, ApiError> {
+        debug!("subscribing to changes of {} after {:?}", key, after);
+
+        let request_params = SignedChangesRequestParams {
+            key,
+            after,
+            signature: None,
+        };
+        let res = self
+            .send_signed_get(
+                &self.stream_client,
+                "/changes",
+                json!(request_params),
+                secret_key,
+            )
+            .await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(ApiError::from_response(
+                status.as_u16(),
+                &res.text().await.unwrap_or_default(),
+            ));
+        }
+
+        let state = (Box::pin(res.bytes_stream()), sse::SseBuffer::default());
+        Ok(stream::unfold(
+            state,
+            |(mut bytes, mut buffer)| async move {
+                loop {
+                    if let Some(event) = buffer.next_event() {
+                        if event.event != "change" {
+                            continue;
+                        }
+                        let change = serde_json::from_str::<Change>(&event.data).map_err(|e| {
+                            ApiError::Decode(format!("change feed sent an invalid change: {}", e))
+                        });
+                        return Some((change, (bytes, buffer)));
+                    }
+                    match bytes.next().await {
+                        Some(Ok(chunk)) => buffer.push(&chunk),
+                        Some(Err(e)) => return Some((Err(e.into()), (bytes, buffer))),
+                        None => return None,
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Exports all contracts and events of `key` and signs the archive with `secret_key`,
+    /// which has to be the secret key of `key`.
+    pub async fn export_archive(
+        &self,
+        key: String,
+        secret_key: SecretKey,
+    ) -> Result<SignedArchive, ApiError> {
+        debug!("exporting everything stored for {}", key);
+
+        let request_params = SignedExportRequestParams {
+            key,
+            signature: None,
+        };
+        let res = self
+            .send_signed_get(&self.client, "/export", json!(request_params), secret_key)
+            .await?;
+        let archive =
+            read_json::<Archive>(res, "export failed, response from API not an archive").await?;
+        let (signature, _pubkey) = self.sign(secret_key, json!(archive).to_string());
+        Ok(SignedArchive { archive, signature })
+    }
+
+    /// Restores an archive from [`Self::export_archive`], possibly into another storage API.
+    /// Each archive can only be imported once, and only for a while after its export.
+    pub async fn import_archive(
+        &self,
+        archive: SignedArchive,
+        secret_key: SecretKey,
+    ) -> Result<ImportSummary, ApiError> {
+        let uri = format!("{}/import", String::as_str(&self.host.clone()));
+        debug!("calling import on url: {:?}", uri);
 
         let (nonce, message_body) = self
-            .build_signed_message(secret_key, json!(contract))
+            .build_signed_message(secret_key, "POST", "/import", json!(archive))
             .await?;
 
         let res = self
             .client
             .post(uri)
             .header("authorization", nonce)
-            .json(&json!(message_body))
+            .header(SIGNATURE_SCHEME_HEADER, self.signature_scheme.as_str())
+            .json(&message_body)
             .send()
             .await?;
+        let summary = read_json::<ImportSummary>(
+            res,
+            "import failed, response from API not an import summary",
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    /// Applies every operation of `batch`, or none of them if one fails; the error then
+    /// says which one. The results are in the order of the operations.
+    pub async fn apply_batch(
+        &self,
+        mut batch: Batch,
+        secret_key: SecretKey,
+    ) -> Result<Vec<BatchResult>, ApiError> {
+        for operation in batch.operations.iter_mut() {
+            match operation {
+                BatchOperation::CreateContract(contract) => {
+                    contract.content = self.seal(
+                        &secret_key,
+                        Row::Contract {
+                            key: &contract.key,
+                            uuid: &contract.uuid,
+                        },
+                        std::mem::take(&mut contract.content),
+                    )?;
+                    contract.metadata = self.reveal(std::mem::take(&mut contract.metadata));
+                }
+                BatchOperation::UpdateContract(contract) => {
+                    let row = Row::Contract {
+                        key: &contract.key,
+                        uuid: &contract.uuid,
+                    };
+                    contract.content = contract
+                        .content
+                        .take()
+                        .map(|content| self.seal(&secret_key, row, content))
+                        .transpose()?;
+                    contract.metadata = self.reveal(std::mem::take(&mut contract.metadata));
+                }
+                BatchOperation::CreateEvent(event) => {
+                    event.content = self.seal(
+                        &secret_key,
+                        Row::Event {
+                            key: &event.key,
+                            event_id: &event.event_id,
+                        },
+                        std::mem::take(&mut event.content),
+                    )?;
+                    event.metadata = self.reveal(std::mem::take(&mut event.metadata));
+                }
+                BatchOperation::UpdateEvent(event) => {
+                    event.content = self.seal(
+                        &secret_key,
+                        Row::Event {
+                            key: &event.key,
+                            event_id: &event.event_id,
+                        },
+                        std::mem::take(&mut event.content),
+                    )?;
+                    event.metadata = self.reveal(std::mem::take(&mut event.metadata));
+                }
+                BatchOperation::DeleteContract(_) | BatchOperation::DeleteEvent(_) => {}
+            }
+        }
+        debug!("calling batch on url: {}/batch", self.host);
+        let res = self
+            .send_signed(Method::POST, "/batch", json!(batch), secret_key)
+            .await?;
+        let mut results = read_json::<BatchResponse>(
+            res,
+            "batch failed, response from API not a list of results",
+        )
+        .await?
+        .results;
+        for result in results.iter_mut() {
+            match result {
+                BatchResult::Contract(contract) => {
+                    contract.content = self.open(
+                        &secret_key,
+                        Row::Contract {
+                            key: &contract.key,
+                            uuid: &contract.uuid,
+                        },
+                        std::mem::take(&mut contract.content),
+                    )?;
+                }
+                BatchResult::Event(event) => {
+                    event.content = self.open(
+                        &secret_key,
+                        Row::Event {
+                            key: &event.key,
+                            event_id: &event.event_id,
+                        },
+                        std::mem::take(&mut event.content),
+                    )?;
+                }
+                BatchResult::Effected { .. } => {}
+            }
+        }
+        Ok(results)
+    }
 
-        let status = res.status().into();
-        let contract = res.json::<Contract>().await.map_err(|e| ApiError {
-            message: format!(
-                "Create contract failed, response from API not an contract object, error: {}",
-                e
-            ),
-            status,
-        })?;
+    pub async fn create_contract(
+        &self,
+        mut contract: NewContract,
+        secret_key: SecretKey,
+    ) -> Result<Contract, ApiError> {
+        contract.content = self.seal(
+            &secret_key,
+            Row::Contract {
+                key: &contract.key,
+                uuid: &contract.uuid,
+            },
+            contract.content,
+        )?;
+        contract.metadata = self.reveal(contract.metadata);
+        let uri: String = format!("{}/contracts", String::as_str(&self.host.clone()));
+        debug!("calling contract create on url: {:?}", uri);
+
+        let res = self
+            .send_signed(Method::POST, "/contracts", json!(contract), secret_key)
+            .await?;
+
+        let mut contract = read_json::<Contract>(
+            res,
+            "Create contract failed, response from API not an contract object",
+        )
+        .await?;
+        contract.content = self.open(
+            &secret_key,
+            Row::Contract {
+                key: &contract.key,
+                uuid: &contract.uuid,
+            },
+            contract.content,
+        )?;
         Ok(contract)
     }
 
     pub async fn create_event(
         &self,
-        event: NewEvent,
+        mut event: NewEvent,
         secret_key: SecretKey,
     ) -> Result<Event, ApiError> {
+        event.content = self.seal(
+            &secret_key,
+            Row::Event {
+                key: &event.key,
+                event_id: &event.event_id,
+            },
+            event.content,
+        )?;
+        event.metadata = self.reveal(event.metadata);
         let uri = format!("{}/events", String::as_str(&self.host.clone()));
         debug!("calling event create on url: {:?}", uri);
 
-        let (nonce, message_body) = self.build_signed_message(secret_key, json!(event)).await?;
-
         let res = self
-            .client
-            .post(uri)
-            .header("authorization", nonce)
-            .json(&message_body)
-            .send()
+            .send_signed(Method::POST, "/events", json!(event), secret_key)
             .await?;
-        let status = res.status().into();
-        let event = res.json::<Event>().await.map_err(|e| ApiError {
-            message: format!(
-                "Create event failed, response from API not an event object, error: {}",
-                e
-            ),
-            status,
-        })?;
+        let mut event = read_json::<Event>(
+            res,
+            "Create event failed, response from API not an event object",
+        )
+        .await?;
+        event.content = self.open(
+            &secret_key,
+            Row::Event {
+                key: &event.key,
+                event_id: &event.event_id,
+            },
+            event.content,
+        )?;
         Ok(event)
     }
 
     pub async fn update_event(
         &self,
-        event: UpdateEvent,
+        mut event: UpdateEvent,
         secret_key: SecretKey,
     ) -> Result<(), ApiError> {
+        event.content = self.seal(
+            &secret_key,
+            Row::Event {
+                key: &event.key,
+                event_id: &event.event_id,
+            },
+            event.content,
+        )?;
+        event.metadata = self.reveal(event.metadata);
         let uri = format!("{}/events", String::as_str(&self.host.clone()));
         debug!("calling event update on url: {:?}", uri);
 
-        let (nonce, message_body) = self.build_signed_message(secret_key, json!(event)).await?;
-
         let res = self
-            .client
-            .put(uri)
-            .header("authorization", nonce)
-            .json(&message_body)
-            .send()
+            .send_signed(Method::PUT, "/events", json!(event), secret_key)
             .await?;
-        let status = res.status().into();
-        match res
-            .json::<EffectedNumResponse>()
-            .await
-            .map_err(|e| ApiError {
-                message: format!(
-                    "Updating event failed, response from API not a number, error: {}",
-                    e
-                ),
-                status,
-            })?
-            .effected_num
+        match read_json::<EffectedNumResponse>(
+            res,
+            "Updating event failed, response from API not a number",
+        )
+        .await?
+        .effected_num
         {
-            0 => Err(ApiError {
-                message: "No event updated".to_string(),
-                status,
-            }),
+            0 => Err(ApiError::NotFound("No event updated".to_string())),
             1 => Ok(()),
             _ => {
                 error!("More than one event updated");
@@ -459,38 +1537,32 @@ impl StorageApiClient {
 
     pub async fn update_contract(
         &self,
-        contract: UpdateContract,
+        mut contract: UpdateContract,
         secret_key: SecretKey,
     ) -> Result<(), ApiError> {
+        let row = Row::Contract {
+            key: &contract.key,
+            uuid: &contract.uuid,
+        };
+        contract.content = contract
+            .content
+            .take()
+            .map(|content| self.seal(&secret_key, row, content))
+            .transpose()?;
+        contract.metadata = self.reveal(contract.metadata);
         let uri = format!("{}/contracts", String::as_str(&self.host.clone()));
         debug!("calling contract update on url: {:?}", uri);
-        let (nonce, message_body) = self
-            .build_signed_message(secret_key, json!(contract))
-            .await?;
         let res = self
-            .client
-            .put(uri)
-            .header("authorization", nonce)
-            .json(&json!(message_body))
-            .send()
+            .send_signed(Method::PUT, "/contracts", json!(contract), secret_key)
             .await?;
-        let status = res.status().into();
-        match res
-            .json::<EffectedNumResponse>()
-            .await
-            .map_err(|e| ApiError {
-                message: format!(
-                    "Updating contract failed, response from API not a number, error: {}",
-                    e
-                ),
-                status,
-            })?
-            .effected_num
+        match read_json::<EffectedNumResponse>(
+            res,
+            "Updating contract failed, response from API not a number",
+        )
+        .await?
+        .effected_num
         {
-            0 => Err(ApiError {
-                message: "No contract updated".to_string(),
-                status,
-            }),
+            0 => Err(ApiError::NotFound("No contract updated".to_string())),
             1 => Ok(()),
             _ => {
                 error!("More than one contract updated");
@@ -507,32 +1579,17 @@ impl StorageApiClient {
         let uri = format!("{}/event", String::as_str(&self.host.clone()));
         debug!("calling event delete on url: {:?}", uri);
 
-        let (nonce, message_body) = self.build_signed_message(secret_key, json!(event)).await?;
-
         let res = self
-            .client
-            .delete(uri)
-            .header("authorization", nonce)
-            .json(&message_body)
-            .send()
+            .send_signed(Method::DELETE, "/event", json!(event), secret_key)
             .await?;
-        let status = res.status().into();
-        match res
-            .json::<EffectedNumResponse>()
-            .await
-            .map_err(|e| ApiError {
-                message: format!(
-                    "Deleting event failed, response from API not a number, error: {}",
-                    e
-                ),
-                status,
-            })?
-            .effected_num
+        match read_json::<EffectedNumResponse>(
+            res,
+            "Deleting event failed, response from API not a number",
+        )
+        .await?
+        .effected_num
         {
-            0 => Err(ApiError {
-                message: "No event deleted".to_string(),
-                status,
-            }),
+            0 => Err(ApiError::NotFound("No event deleted".to_string())),
             1 => Ok(()),
             _ => {
                 error!("More than one event deleted");
@@ -548,33 +1605,17 @@ impl StorageApiClient {
     ) -> Result<(), ApiError> {
         let uri = format!("{}/contract", String::as_str(&self.host.clone()));
         debug!("calling contract delete on url: {:?}", uri);
-        let (nonce, message_body) = self
-            .build_signed_message(secret_key, json!(contract))
-            .await?;
         let res = self
-            .client
-            .delete(uri)
-            .header("authorization", nonce)
-            .json(&json!(message_body))
-            .send()
+            .send_signed(Method::DELETE, "/contract", json!(contract), secret_key)
             .await?;
-        let status = res.status().into();
-        match res
-            .json::<EffectedNumResponse>()
-            .await
-            .map_err(|e| ApiError {
-                message: format!(
-                    "Deleting contract failed, response from API not a number, error: {}",
-                    e
-                ),
-                status,
-            })?
-            .effected_num
+        match read_json::<EffectedNumResponse>(
+            res,
+            "Deleting contract failed, response from API not a number",
+        )
+        .await?
+        .effected_num
         {
-            0 => Err(ApiError {
-                message: "No contract deleted".to_string(),
-                status,
-            }),
+            0 => Err(ApiError::NotFound("No contract deleted".to_string())),
             1 => Ok(()),
             _ => {
                 error!("More than one contract deleted");
@@ -583,6 +1624,110 @@ impl StorageApiClient {
         }
     }
 
+    /// Brings back a deleted contract, as long as it has not been purged yet.
+    pub async fn restore_contract(
+        &self,
+        contract: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        self.restore("/contract/restore", json!(contract), secret_key)
+            .await
+    }
+
+    /// Brings back a deleted event, as long as it has not been purged yet.
+    pub async fn restore_event(
+        &self,
+        event: EventRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        self.restore("/event/restore", json!(event), secret_key)
+            .await
+    }
+
+    async fn restore(
+        &self,
+        path: &str,
+        body: Value,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        debug!("calling restore on url: {}{}", self.host, path);
+        let res = self
+            .send_signed(Method::POST, path, body, secret_key)
+            .await?;
+        read_json::<EffectedNumResponse>(res, "Restoring failed, response from API not a number")
+            .await?;
+        Ok(())
+    }
+
+    /// Every key registered with the API. `secret_key` has to be the API's admin key.
+    pub async fn get_registered_keys(
+        &self,
+        secret_key: SecretKey,
+    ) -> Result<Vec<RegisteredKey>, ApiError> {
+        let request_params = SignedExportRequestParams {
+            key: self.public_key(secret_key),
+            signature: None,
+        };
+        let res = self
+            .send_signed_get(
+                &self.client,
+                "/admin/keys",
+                json!(request_params),
+                secret_key,
+            )
+            .await?;
+        read_json::<Vec<RegisteredKey>>(
+            res,
+            "get registered keys failed, response from API not a list of keys",
+        )
+        .await
+    }
+
+    /// Registers `public_key` with `role`, or changes the role it has. `secret_key` has to
+    /// be the API's admin key.
+    pub async fn register_key(
+        &self,
+        public_key: String,
+        role: Role,
+        secret_key: SecretKey,
+    ) -> Result<RegisteredKey, ApiError> {
+        let body = RegisterKey {
+            key: self.public_key(secret_key),
+            public_key,
+            role,
+        };
+        let res = self
+            .send_signed(Method::POST, "/admin/keys", json!(body), secret_key)
+            .await?;
+        read_json::<RegisteredKey>(
+            res,
+            "register key failed, response from API not a registered key",
+        )
+        .await
+    }
+
+    /// Removes `public_key` from the registered keys, what it stored is kept. `secret_key`
+    /// has to be the API's admin key.
+    pub async fn unregister_key(
+        &self,
+        public_key: String,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        let body = UnregisterKey {
+            key: self.public_key(secret_key),
+            public_key,
+        };
+        let res = self
+            .send_signed(Method::DELETE, "/admin/keys", json!(body), secret_key)
+            .await?;
+        read_json::<EffectedNumResponse>(
+            res,
+            "unregister key failed, response from API not a number",
+        )
+        .await?;
+        Ok(())
+    }
+
     // For testing only, should be removed
     // pub async fn delete_contracts(&self, key: String) -> Result<(), ApiError> {
     //     self.delete_resources("contracts".to_string(), key).await
@@ -694,7 +1839,7 @@ mod tests {
 
         // Build signed message
         let (nonce, signed_message) = client
-            .build_signed_message(secret_key, contract_wo_nonce)
+            .build_signed_message(secret_key, "POST", "/contracts", contract_wo_nonce)
             .await
             .expect("should be able to build signed message");
 
@@ -703,7 +1848,8 @@ mod tests {
 
         // Verify nonce and signature
         assert_eq!(nonce, expected_nonce);
-        let digest = Message::from(sha256::Hash::hash(contract_w_nonce.to_string().as_bytes()));
+        let payload = format!("POST\n/contracts\n{}\n{}", nonce, contract_w_nonce);
+        let digest = Message::from(sha256::Hash::hash(payload.as_bytes()));
 
         assert!(secp
             .verify_ecdsa(
@@ -714,4 +1860,967 @@ mod tests {
             )
             .is_ok());
     }
+
+    #[test]
+    fn test_query_signature_covers_method_path_and_query() {
+        let client = StorageApiClient::new("http://localhost".to_string());
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let params = SignedContractsRequestParams {
+            key: "k1".to_string(),
+            uuid: Some("123".to_string()),
+            ..Default::default()
+        };
+        let signature: secp256k1_zkp::ecdsa::Signature = client
+            .sign_query(secret_key, "/contracts", "abcde", &params)
+            .expect("should sign")
+            .parse()
+            .expect("should parse signature");
+
+        // the storage API rebuilds this from the request it receives
+        let payload = "GET\n/contracts\nabcde\nkey=k1&uuid=123";
+        let digest = Message::from(sha256::Hash::hash(payload.as_bytes()));
+        assert!(secp
+            .verify_ecdsa(&digest, &signature, &secret_key.public_key(&secp))
+            .is_ok());
+        assert_eq!(
+            canonical_query("uuid=1+2&signature=x&key=k%2F1"),
+            "key=k%2F1&uuid=1+2"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_session_token_is_reused() {
+        let mut server = mockito::Server::new_async().await;
+        let nonce = server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .expect(1)
+            .create_async()
+            .await;
+        let login = server
+            .mock("POST", "/login")
+            .match_header("authorization", "abcde")
+            .with_status(200)
+            .with_body(json!({"token": "t1", "key": "k1", "expires_in_secs": 900}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let events = server
+            .mock("GET", "/events")
+            .match_header("authorization", "Bearer t1")
+            .match_query(mockito::Matcher::UrlEncoded("key".into(), "k1".into()))
+            .with_status(200)
+            .with_body("[]")
+            .expect(2)
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        for _ in 0..2 {
+            client
+                .get_events(
+                    EventsRequestParams {
+                        key: "k1".to_string(),
+                        ..Default::default()
+                    },
+                    secret_key,
+                )
+                .await
+                .expect("should get events");
+        }
+
+        // one nonce for the login, none for the requests
+        nonce.assert_async().await;
+        login.assert_async().await;
+        events.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_login_backs_off() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let login = server
+            .mock("POST", "/login")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let signed = server
+            .mock("GET", "/events")
+            .match_header("authorization", "abcde")
+            .match_query(mockito::Matcher::Regex("signature=".into()))
+            .with_status(200)
+            .with_body("[]")
+            .expect(3)
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        for _ in 0..3 {
+            client
+                .get_events(
+                    EventsRequestParams {
+                        key: "k1".to_string(),
+                        ..Default::default()
+                    },
+                    secret_key,
+                )
+                .await
+                .expect("should get events");
+        }
+
+        // one login, then signed requests until the backoff has passed
+        login.assert_async().await;
+        signed.assert_async().await;
+        let sessions = client.sessions.lock().expect("should lock sessions");
+        assert!(!sessions.may_login());
+        assert_eq!(sessions.login_failures, 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_apply_batch() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/login")
+            .with_status(200)
+            .with_body(json!({"token": "t1", "key": "k1", "expires_in_secs": 900}).to_string())
+            .create_async()
+            .await;
+        let batch = server
+            .mock("POST", "/batch")
+            .match_header("authorization", "Bearer t1")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::Regex(r#""key":"k1""#.into()),
+                mockito::Matcher::Regex(r#""op":"create_event".*"op":"delete_contract""#.into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                json!({"results": [
+                    {"id": 1, "event_id": "e1", "content": "YWJj", "key": "k1"},
+                    {"effected_num": 1},
+                ]})
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let operations = Batch::new("k1".to_string())
+            .create_event(NewEvent {
+                event_id: "e1".to_string(),
+                content: "abc".to_string(),
+                key: "k1".to_string(),
+                metadata: EventMetadata::default(),
+            })
+            .delete_contract(ContractRequestParams {
+                key: "k1".to_string(),
+                uuid: "c1".to_string(),
+            });
+        assert_eq!(operations.len(), 2);
+        let results = client
+            .apply_batch(operations, secret_key)
+            .await
+            .expect("should apply batch");
+
+        batch.assert_async().await;
+        assert!(
+            matches!(&results[0], BatchResult::Event(event) if event.content == "YWJj"),
+            "{:?}",
+            results
+        );
+        assert!(matches!(
+            results[1],
+            BatchResult::Effected { effected_num: 1 }
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn test_register_key() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/login")
+            .with_status(404)
+            .create_async()
+            .await;
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let admin_key = client.public_key(secret_key);
+        let register = server
+            .mock("POST", "/admin/keys")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::Regex(format!(r#""key":"{}""#, admin_key)),
+                mockito::Matcher::Regex(r#""public_key":"k1""#.into()),
+                mockito::Matcher::Regex(r#""role":"router_wallet""#.into()),
+            ]))
+            .with_status(200)
+            .with_body(json!({"key": "k1", "role": "router_wallet", "created_at": 1}).to_string())
+            .create_async()
+            .await;
+
+        let registered = client
+            .register_key("k1".to_string(), Role::RouterWallet, secret_key)
+            .await
+            .expect("should register key");
+
+        register.assert_async().await;
+        assert_eq!(registered.role, Role::RouterWallet);
+    }
+
+    #[actix_rt::test]
+    async fn test_expired_session_falls_back_to_signing() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/login")
+            .with_status(200)
+            .with_body(json!({"token": "t1", "key": "k1", "expires_in_secs": 900}).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/events")
+            .match_header("authorization", "Bearer t1")
+            .match_query(mockito::Matcher::Any)
+            .with_status(401)
+            .create_async()
+            .await;
+        let signed = server
+            .mock("GET", "/events")
+            .match_header("authorization", "abcde")
+            .match_query(mockito::Matcher::Regex("signature=".into()))
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        client
+            .get_events(
+                EventsRequestParams {
+                    key: "k1".to_string(),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await
+            .expect("should get events");
+
+        signed.assert_async().await;
+        assert!(client
+            .sessions
+            .lock()
+            .expect("should lock sessions")
+            .tokens
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_subscribe_changes() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let feed = server
+            .mock("GET", "/changes")
+            .match_header("authorization", "abcde")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "after".to_string(),
+                "1".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                ": keepalive\n\n",
+                "id: 2\nevent: change\ndata: {\"id\":2,\"key\":\"k1\",\"entity\":\"contract\",\"entity_id\":\"123\",\"kind\":\"updated\",\"state\":\"signed\"}\n\n",
+                "id: 3\nevent: change\ndata: {\"id\":3,\"key\":\"k1\",\"entity\":\"event\",\"entity_id\":\"e1\",\"kind\":\"deleted\",\"state\":null}\n\n",
+            ))
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let changes: Vec<Change> = client
+            .subscribe_changes("k1".to_string(), Some(1), secret_key)
+            .await
+            .expect("should subscribe")
+            .map(|change| change.expect("should parse change"))
+            .collect()
+            .await;
+
+        feed.assert_async().await;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].kind, ChangeKind::Updated);
+        assert_eq!(changes[0].state.as_deref(), Some("signed"));
+        assert_eq!(changes[1].entity, ChangeEntity::Event);
+        assert_eq!(changes[1].id, 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_export_archive_is_signed() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let archive = json!({
+            "version": 2,
+            "key": "k1",
+            "exported_at": 1700000000,
+            "nonce": "n1",
+            "contracts": [{"uuid": "123", "state": "signed", "content": "abc"}],
+            "events": [],
+        });
+        server
+            .mock("GET", "/export")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(archive.to_string())
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let signed_archive = client
+            .export_archive("k1".to_string(), secret_key)
+            .await
+            .expect("should export");
+
+        assert_eq!(signed_archive.archive.contracts[0].state, "signed");
+        let digest = Message::from(sha256::Hash::hash(archive.to_string().as_bytes()));
+        let signature: secp256k1_zkp::ecdsa::Signature = signed_archive
+            .signature
+            .parse()
+            .expect("should parse signature");
+        assert!(secp
+            .verify_ecdsa(&digest, &signature, &secret_key.public_key(&secp))
+            .is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_schnorr_signed_export() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let (x_only_key, _parity) =
+            KeyPair::from_secret_key(&secp, &secret_key).x_only_public_key();
+        let archive = json!({
+            "version": 2,
+            "key": x_only_key.to_string(),
+            "exported_at": 1700000000,
+            "nonce": "n1",
+            "contracts": [],
+            "events": [],
+        });
+        let export = server
+            .mock("GET", "/export")
+            .match_header(SIGNATURE_SCHEME_HEADER, "schnorr")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(archive.to_string())
+            .create_async()
+            .await;
+
+        let client =
+            StorageApiClient::new(server.url()).with_signature_scheme(SignatureScheme::Schnorr);
+        let signed_archive = client
+            .export_archive(x_only_key.to_string(), secret_key)
+            .await
+            .expect("should export");
+
+        export.assert_async().await;
+        let digest = Message::from(sha256::Hash::hash(archive.to_string().as_bytes()));
+        let signature: secp256k1_zkp::schnorr::Signature = signed_archive
+            .signature
+            .parse()
+            .expect("should parse signature");
+        assert!(secp
+            .verify_schnorr(&signature, &digest, &x_only_key)
+            .is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_encrypted_and_legacy_events_are_read() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let row = Row::Event {
+            key: "k1",
+            event_id: "e1",
+        };
+        let encrypted = ContentCipher::new(&secret_key)
+            .encrypt(row, "c2VjcmV0")
+            .expect("should encrypt");
+        let events = json!([
+            {"id": 1, "event_id": "e1", "content": encrypted, "key": "k1"},
+            {"id": 2, "event_id": "e2", "content": "bGVnYWN5", "key": "k1"},
+        ]);
+        server
+            .mock("GET", "/events")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(events.to_string())
+            .create_async()
+            .await;
+
+        // decrypting does not depend on encryption being turned on
+        let client = StorageApiClient::new(server.url());
+        let events = client
+            .get_events(
+                EventsRequestParams {
+                    key: "k1".to_string(),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await
+            .expect("should get events");
+
+        assert_eq!(events[0].content, "c2VjcmV0");
+        assert_eq!(events[1].content, "bGVnYWN5");
+    }
+    #[actix_rt::test]
+    async fn test_event_metadata_filters_and_fields() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let events = server
+            .mock("GET", "/events")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("attested".into(), "false".into()),
+                mockito::Matcher::UrlEncoded("matures_before".into(), "1700000000".into()),
+                mockito::Matcher::Regex("signature=".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                json!([{
+                    "id": 1,
+                    "event_id": "e1",
+                    "content": "YWJj",
+                    "key": "k1",
+                    "maturity": 1600000000,
+                    "attested": false,
+                    "chain": "eth",
+                }])
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let found = client
+            .get_events(
+                EventsRequestParams {
+                    key: "k1".to_string(),
+                    attested: Some(false),
+                    matures_before: Some(1_700_000_000),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await
+            .expect("should get events");
+
+        events.assert_async().await;
+        assert_eq!(
+            found[0].metadata,
+            EventMetadata {
+                maturity: Some(1_600_000_000),
+                attested: Some(false),
+                outcome: None,
+                chain: Some("eth".to_string()),
+            }
+        );
+        // metadata is sent flat, and unset fields not at all
+        let update = UpdateEvent {
+            event_id: "e1".to_string(),
+            content: "YWJj".to_string(),
+            key: "k1".to_string(),
+            metadata: EventMetadata {
+                attested: Some(true),
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            json!(update),
+            json!({"event_id": "e1", "content": "YWJj", "key": "k1", "attested": true})
+        );
+    }
+    #[actix_rt::test]
+    async fn test_contract_metadata_filters() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let contracts = server
+            .mock("GET", "/contracts")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("state".into(), "signed,confirmed".into()),
+                mockito::Matcher::UrlEncoded("counterparty".into(), "p1".into()),
+                mockito::Matcher::UrlEncoded("collateral_min".into(), "1000".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                json!([{
+                    "id": 1,
+                    "uuid": "c1",
+                    "state": "signed",
+                    "content": "YWJj",
+                    "key": "k1",
+                    "counterparty": "p1",
+                    "collateral": 5000,
+                }])
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let found = client
+            .get_contracts(
+                ContractsRequestParams {
+                    key: "k1".to_string(),
+                    state: Some("signed,confirmed".to_string()),
+                    counterparty: Some("p1".to_string()),
+                    collateral_min: Some(1000),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await
+            .expect("should get contracts");
+
+        contracts.assert_async().await;
+        assert_eq!(found[0].metadata.collateral, Some(5000));
+        assert_eq!(found[0].metadata.funding_txid, None);
+    }
+    #[actix_rt::test]
+    async fn test_encrypted_contracts_hold_back_metadata() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let update = UpdateContract {
+            uuid: "u1".to_string(),
+            state: Some("signed".to_string()),
+            content: Some("YWJj".to_string()),
+            key: "k1".to_string(),
+            metadata: ContractMetadata {
+                counterparty: Some("k2".to_string()),
+                ..Default::default()
+            },
+        };
+
+        let held_back = server
+            .mock("PUT", "/contracts")
+            .match_request(|request| {
+                !request
+                    .utf8_lossy_body()
+                    .expect("should read body")
+                    .contains("counterparty")
+            })
+            .with_status(200)
+            .with_body(json!({"effected_num": 1}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let client = StorageApiClient::new(server.url()).with_content_encryption();
+        client
+            .update_contract(update.clone(), secret_key)
+            .await
+            .expect("should update contract");
+        held_back.assert_async().await;
+
+        let sent = server
+            .mock("PUT", "/contracts")
+            .match_body(mockito::Matcher::Regex(r#""counterparty":"k2""#.into()))
+            .with_status(200)
+            .with_body(json!({"effected_num": 1}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client.with_plaintext_metadata();
+        client
+            .update_contract(update, secret_key)
+            .await
+            .expect("should update contract");
+        sent.assert_async().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_retries_and_circuit_breaker() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/login")
+            .with_status(404)
+            .create_async()
+            .await;
+        // one for the login, then one for every attempt
+        let nonces = server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .expect(3)
+            .create_async()
+            .await;
+        let unavailable = server
+            .mock("GET", "/events")
+            .match_query(mockito::Matcher::Any)
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let events = server
+            .mock("GET", "/events")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            failure_threshold: 2,
+            ..Default::default()
+        };
+        let client = StorageApiClient::new(server.url()).with_retry_policy(policy);
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let found = client
+            .get_events(
+                EventsRequestParams {
+                    key: "k1".to_string(),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await
+            .expect("should get events after a retry");
+        assert!(found.is_empty());
+        nonces.assert_async().await;
+        unavailable.assert_async().await;
+        events.assert_async().await;
+
+        // creates are not retried, but count towards the circuit breaker
+        let creates = server
+            .mock("POST", "/events")
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+        let event = NewEvent {
+            event_id: "e1".to_string(),
+            content: "abc".to_string(),
+            key: "k1".to_string(),
+            metadata: EventMetadata::default(),
+        };
+        for _ in 0..2 {
+            let err = client
+                .create_event(event.clone(), secret_key)
+                .await
+                .expect_err("should fail while the API is unavailable");
+            assert!(
+                matches!(err, ApiError::Server { status: 503, .. }),
+                "{}",
+                err
+            );
+        }
+        let err = client
+            .create_event(event, secret_key)
+            .await
+            .expect_err("should fail fast while the breaker is open");
+        assert!(matches!(err, ApiError::Transport(_)), "{}", err);
+        creates.assert_async().await;
+    }
+
+    #[test]
+    fn test_error_from_response() {
+        let body = json!({"error": {"code": "not_found", "message": "No contract found"}});
+        assert_eq!(
+            ApiError::from_response(404, &body.to_string()),
+            ApiError::NotFound("No contract found".to_string())
+        );
+        assert_eq!(
+            ApiError::from_response(401, "expired"),
+            ApiError::Unauthorized("expired".to_string())
+        );
+        assert_eq!(
+            ApiError::from_response(400, ""),
+            ApiError::Server {
+                status: 400,
+                message: "".to_string()
+            }
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_update_of_missing_contract_is_not_found() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/request_nonce")
+            .with_status(200)
+            .with_body("abcde")
+            .create_async()
+            .await;
+        server
+            .mock("PUT", "/contracts")
+            .with_status(404)
+            .with_body(
+                json!({"error": {"code": "not_found", "message": "No contract found"}}).to_string(),
+            )
+            .create_async()
+            .await;
+        server
+            .mock("PUT", "/events")
+            .with_status(200)
+            .with_body("not a number")
+            .create_async()
+            .await;
+
+        let client = StorageApiClient::new(server.url());
+        let secret_key = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        let err = client
+            .update_contract(
+                UpdateContract {
+                    uuid: "c1".to_string(),
+                    state: Some("signed".to_string()),
+                    content: None,
+                    key: "k1".to_string(),
+                    metadata: ContractMetadata::default(),
+                },
+                secret_key,
+            )
+            .await
+            .expect_err("should not update a missing contract");
+        assert_eq!(err, ApiError::NotFound("No contract found".to_string()));
+
+        let err = client
+            .update_event(
+                UpdateEvent {
+                    event_id: "e1".to_string(),
+                    content: "abc".to_string(),
+                    key: "k1".to_string(),
+                    metadata: EventMetadata::default(),
+                },
+                secret_key,
+            )
+            .await
+            .expect_err("should not read an invalid response");
+        assert!(matches!(err, ApiError::Decode(_)), "{}", err);
+    }
+
+    /// The storage API's OpenAPI document, regenerated with `just openapi` in `storage`.
+    const OPENAPI_JSON: &str = include_str!("../../storage/openapi.json");
+
+    /// A value for `schema` at the edge of its type, so that a client field of a narrower
+    /// type (or an unsigned one for a signed column) can't read it.
+    fn sample(spec: &Value, schema: &Value) -> Value {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return sample(spec, &spec["components"]["schemas"][name]);
+        }
+        let kind = match &schema["type"] {
+            Value::Array(kinds) => kinds.iter().find(|kind| *kind != "null"),
+            kind => Some(kind),
+        };
+        let unsigned = schema["minimum"] == 0;
+        match (kind.and_then(Value::as_str), schema["format"].as_str()) {
+            (Some("object"), _) => Value::Object(
+                schema["properties"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, property)| (name.clone(), sample(spec, property)))
+                    .collect(),
+            ),
+            (Some("array"), _) => json!([sample(spec, &schema["items"])]),
+            _ if schema["allOf"].is_array() => {
+                let mut merged = json!({});
+                for part in schema["allOf"].as_array().into_iter().flatten() {
+                    for (name, value) in sample(spec, part).as_object().into_iter().flatten() {
+                        merged[name] = value.clone();
+                    }
+                }
+                merged
+            }
+            _ if schema["oneOf"].is_array() => sample(spec, &schema["oneOf"][0]),
+            (Some("integer"), Some("int32")) if unsigned => json!(u32::MAX),
+            (Some("integer"), Some("int32")) => json!(i32::MIN),
+            (Some("integer"), _) if unsigned => json!(u64::MAX),
+            (Some("integer"), _) => json!(i64::MIN),
+            (Some("boolean"), _) => json!(true),
+            (Some("string"), _) if schema["enum"].is_array() => schema["enum"][0].clone(),
+            (Some("string"), _) => json!("s"),
+            other => panic!("no sample for {:?}", other),
+        }
+    }
+
+    /// The query parameters of a GET as an object schema.
+    fn query_schema(spec: &Value, path: &str) -> Value {
+        let properties: serde_json::Map<String, Value> = spec["paths"][path]["get"]["parameters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|parameter| parameter["in"] == "query")
+            .map(|parameter| {
+                let name = parameter["name"].as_str().unwrap_or_default().to_string();
+                (name, parameter["schema"].clone())
+            })
+            .collect();
+        json!({"type": "object", "properties": properties})
+    }
+
+    /// Reads a sample of `schema` into `T` and writes it back, which only gives the same
+    /// JSON if `T` has every field of the schema, of a type that fits, and no others.
+    fn assert_matches<T: serde::de::DeserializeOwned + serde::Serialize>(
+        spec: &Value,
+        name: &str,
+        schema: &Value,
+        overrides: Value,
+    ) {
+        let mut expected = sample(spec, schema);
+        for (field, value) in overrides.as_object().into_iter().flatten() {
+            expected[field] = value.clone();
+        }
+        let value: T = serde_json::from_value(expected.clone())
+            .unwrap_or_else(|e| panic!("{} can't read the API's {}: {}", name, expected, e));
+        let written = serde_json::to_value(value).expect("should serialize");
+        assert_eq!(written, expected, "{} drifted from the API", name);
+    }
+
+    #[test]
+    fn test_types_match_openapi() {
+        let spec: Value = serde_json::from_str(OPENAPI_JSON).expect("should parse openapi.json");
+        let schema = |name: &str| spec["components"]["schemas"][name].clone();
+        macro_rules! check {
+            ($client:ty, $schema:expr) => {
+                check!($client, $schema, json!({}))
+            };
+            ($client:ty, $schema:expr, $overrides:expr) => {
+                assert_matches::<$client>(&spec, stringify!($client), &$schema, $overrides)
+            };
+        }
+
+        check!(Contract, schema("Contract"));
+        check!(NewContract, schema("NewContract"));
+        check!(UpdateContract, schema("UpdateContract"));
+        check!(ContractRequestParams, schema("DeleteContract"));
+        check!(Event, schema("Event"));
+        check!(NewEvent, schema("NewEvent"));
+        check!(UpdateEvent, schema("UpdateEvent"));
+        check!(EventRequestParams, schema("DeleteEvent"));
+        check!(
+            Change,
+            schema("Change"),
+            json!({"entity": "contract", "kind": "created"})
+        );
+        check!(SignedArchive, schema("SignedArchive"));
+        check!(ImportSummary, schema("ImportSummary"));
+        check!(EffectedNumResponse, schema("EffectedNum"));
+        check!(RegisterKey, schema("RegisterKey"));
+        check!(UnregisterKey, schema("UnregisterKey"));
+        check!(
+            RegisteredKey,
+            schema("RegisteredKey"),
+            json!({"role": "wallet"})
+        );
+        check!(Batch, schema("BatchRequest"));
+        for operation in schema("BatchOperation")["oneOf"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            check!(BatchOperation, operation);
+        }
+        for result in schema("BatchResult")["oneOf"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            check!(BatchResult, result);
+        }
+
+        // signed GETs carry their signature next to the documented parameters
+        let signature = json!({"signature": "s"});
+        check!(
+            SignedContractsRequestParams,
+            query_schema(&spec, "/contracts"),
+            signature.clone()
+        );
+        check!(
+            SignedEventsRequestParams,
+            query_schema(&spec, "/events"),
+            signature.clone()
+        );
+        check!(
+            SignedChangesRequestParams,
+            query_schema(&spec, "/changes"),
+            signature.clone()
+        );
+        check!(
+            SignedExportRequestParams,
+            query_schema(&spec, "/export"),
+            signature.clone()
+        );
+        check!(
+            SignedExportRequestParams,
+            query_schema(&spec, "/admin/keys"),
+            signature
+        );
+    }
 }
@@ -0,0 +1,462 @@
+use std::sync::{Arc, Mutex};
+
+use secp256k1_zkp::SecretKey;
+
+use crate::storage_api::StorageApi;
+use crate::{
+    ApiError, Contract, ContractRequestParams, ContractsRequestParams, Event, EventRequestParams,
+    EventsRequestParams, NewContract, NewEvent, SignatureScheme, UpdateContract, UpdateEvent,
+};
+
+/// What is stored in a [`MemoryStorageApi`]. Timestamps come from a counter that ticks
+/// on every write, not from a clock.
+#[derive(Debug, Default)]
+struct Store {
+    contracts: Vec<Contract>,
+    events: Vec<Event>,
+    next_id: i32,
+}
+
+impl Store {
+    fn tick(&mut self) -> i32 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+/// A [`StorageApi`] that keeps everything in memory, for tests that should run without
+/// a storage API.
+///
+/// Like the API, it only lets a secret key read and write the data of its own public
+/// key, answers writes of missing contracts and events with a 404 and creates of
+/// existing ones with a 409, and soft deletes. Content is stored as it is given, never
+/// encrypted. Clones share their data, like clients of the same API.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorageApi {
+    store: Arc<Mutex<Store>>,
+    signature_scheme: SignatureScheme,
+}
+
+impl MemoryStorageApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expects keys encoded for `scheme`, like [`StorageApiClient::with_signature_scheme`].
+    ///
+    /// [`StorageApiClient::with_signature_scheme`]: crate::StorageApiClient::with_signature_scheme
+    pub fn with_signature_scheme(mut self, scheme: SignatureScheme) -> Self {
+        self.signature_scheme = scheme;
+        self
+    }
+
+    /// Fails like the API does when a request for `key` is signed by another key.
+    fn authorize(&self, key: &str, secret_key: SecretKey) -> Result<(), ApiError> {
+        if self.signature_scheme.public_key(secret_key) == key {
+            Ok(())
+        } else {
+            Err(ApiError::Unauthorized(
+                "invalid signature or nonce".to_string(),
+            ))
+        }
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().expect("Failed to lock memory storage")
+    }
+}
+
+/// Whether `value` is set and matches `filter`, or there is no filter.
+fn matches<T: PartialEq>(filter: &Option<T>, value: &Option<T>) -> bool {
+    filter.is_none() || filter == value
+}
+
+impl StorageApi for MemoryStorageApi {
+    async fn get_contracts(
+        &self,
+        contract_req: ContractsRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Vec<Contract>, ApiError> {
+        self.authorize(&contract_req.key, secret_key)?;
+        let states = contract_req
+            .state
+            .as_ref()
+            .map(|states| states.split(',').collect::<Vec<_>>());
+        Ok(self
+            .store()
+            .contracts
+            .iter()
+            .filter(|c| c.key == contract_req.key)
+            .filter(|c| contract_req.include_deleted == Some(true) || c.deleted_at.is_none())
+            .filter(|c| {
+                contract_req
+                    .uuid
+                    .as_ref()
+                    .map_or(true, |uuid| &c.uuid == uuid)
+            })
+            .filter(|c| {
+                states
+                    .as_ref()
+                    .map_or(true, |states| states.contains(&c.state.as_str()))
+            })
+            .filter(|c| matches(&contract_req.oracle_event_id, &c.metadata.oracle_event_id))
+            .filter(|c| matches(&contract_req.funding_txid, &c.metadata.funding_txid))
+            .filter(|c| matches(&contract_req.counterparty, &c.metadata.counterparty))
+            .filter(|c| {
+                let collateral = c.metadata.collateral;
+                contract_req
+                    .collateral_min
+                    .map_or(true, |min| collateral.is_some_and(|c| c >= min))
+                    && contract_req
+                        .collateral_max
+                        .map_or(true, |max| collateral.is_some_and(|c| c <= max))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_contract(
+        &self,
+        contract_req: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Option<Contract>, ApiError> {
+        let contracts = self
+            .get_contracts(
+                ContractsRequestParams {
+                    key: contract_req.key,
+                    uuid: Some(contract_req.uuid),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await?;
+        Ok(contracts.first().cloned())
+    }
+
+    async fn create_contract(
+        &self,
+        contract: NewContract,
+        secret_key: SecretKey,
+    ) -> Result<Contract, ApiError> {
+        self.authorize(&contract.key, secret_key)?;
+        let mut store = self.store();
+        let existing = store
+            .contracts
+            .iter()
+            .position(|c| c.key == contract.key && c.uuid == contract.uuid);
+        if let Some(index) = existing {
+            if store.contracts[index].deleted_at.is_none() {
+                return Err(ApiError::Conflict("contract already exists".to_string()));
+            }
+            // like the API, a new contract replaces a deleted one with its uuid
+            store.contracts.remove(index);
+        }
+        let id = store.tick();
+        let created = Contract {
+            id,
+            uuid: contract.uuid,
+            state: contract.state,
+            content: contract.content,
+            key: contract.key,
+            metadata: contract.metadata,
+            deleted_at: None,
+            updated_at: Some(i64::from(id)),
+        };
+        store.contracts.push(created.clone());
+        Ok(created)
+    }
+
+    async fn update_contract(
+        &self,
+        contract: UpdateContract,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        self.authorize(&contract.key, secret_key)?;
+        let mut store = self.store();
+        let now = store.tick();
+        let stored = store
+            .contracts
+            .iter_mut()
+            .find(|c| c.key == contract.key && c.uuid == contract.uuid && c.deleted_at.is_none())
+            .ok_or_else(|| ApiError::NotFound("No contract found".to_string()))?;
+        if let Some(state) = contract.state {
+            stored.state = state;
+        }
+        if let Some(content) = contract.content {
+            stored.content = content;
+        }
+        let metadata = contract.metadata;
+        stored.metadata.oracle_event_id = metadata
+            .oracle_event_id
+            .or(stored.metadata.oracle_event_id.take());
+        stored.metadata.funding_txid = metadata
+            .funding_txid
+            .or(stored.metadata.funding_txid.take());
+        stored.metadata.counterparty = metadata
+            .counterparty
+            .or(stored.metadata.counterparty.take());
+        stored.metadata.collateral = metadata.collateral.or(stored.metadata.collateral);
+        stored.updated_at = Some(i64::from(now));
+        Ok(())
+    }
+
+    async fn delete_contract(
+        &self,
+        contract: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        self.authorize(&contract.key, secret_key)?;
+        let mut store = self.store();
+        let now = store.tick();
+        let stored = store
+            .contracts
+            .iter_mut()
+            .find(|c| c.key == contract.key && c.uuid == contract.uuid && c.deleted_at.is_none())
+            .ok_or_else(|| ApiError::NotFound("No contract found".to_string()))?;
+        stored.deleted_at = Some(i64::from(now));
+        Ok(())
+    }
+
+    async fn get_events(
+        &self,
+        event_req: EventsRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Vec<Event>, ApiError> {
+        self.authorize(&event_req.key, secret_key)?;
+        Ok(self
+            .store()
+            .events
+            .iter()
+            .filter(|e| e.key == event_req.key)
+            .filter(|e| event_req.include_deleted == Some(true) || e.deleted_at.is_none())
+            .filter(|e| {
+                event_req
+                    .event_id
+                    .as_ref()
+                    .map_or(true, |event_id| &e.event_id == event_id)
+            })
+            .filter(|e| matches(&event_req.attested, &e.metadata.attested))
+            .filter(|e| matches(&event_req.outcome, &e.metadata.outcome))
+            .filter(|e| matches(&event_req.chain, &e.metadata.chain))
+            .filter(|e| {
+                let maturity = e.metadata.maturity;
+                event_req
+                    .matures_before
+                    .map_or(true, |before| maturity.is_some_and(|m| m < before))
+                    && event_req
+                        .matures_after
+                        .map_or(true, |after| maturity.is_some_and(|m| m > after))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_event(
+        &self,
+        event_req: EventRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Option<Event>, ApiError> {
+        let events = self
+            .get_events(
+                EventsRequestParams {
+                    key: event_req.key,
+                    event_id: Some(event_req.event_id),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await?;
+        Ok(events.first().cloned())
+    }
+
+    async fn create_event(
+        &self,
+        event: NewEvent,
+        secret_key: SecretKey,
+    ) -> Result<Event, ApiError> {
+        self.authorize(&event.key, secret_key)?;
+        let mut store = self.store();
+        let existing = store
+            .events
+            .iter()
+            .position(|e| e.key == event.key && e.event_id == event.event_id);
+        if let Some(index) = existing {
+            if store.events[index].deleted_at.is_none() {
+                return Err(ApiError::Conflict("event already exists".to_string()));
+            }
+            store.events.remove(index);
+        }
+        let created = Event {
+            id: store.tick(),
+            event_id: event.event_id,
+            content: event.content,
+            key: event.key,
+            metadata: event.metadata,
+            deleted_at: None,
+        };
+        store.events.push(created.clone());
+        Ok(created)
+    }
+
+    async fn update_event(
+        &self,
+        event: UpdateEvent,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        self.authorize(&event.key, secret_key)?;
+        let mut store = self.store();
+        let stored = store
+            .events
+            .iter_mut()
+            .find(|e| e.key == event.key && e.event_id == event.event_id && e.deleted_at.is_none())
+            .ok_or_else(|| ApiError::NotFound("No event found".to_string()))?;
+        stored.content = event.content;
+        let metadata = event.metadata;
+        stored.metadata.maturity = metadata.maturity.or(stored.metadata.maturity);
+        stored.metadata.attested = metadata.attested.or(stored.metadata.attested);
+        stored.metadata.outcome = metadata.outcome.or(stored.metadata.outcome.take());
+        stored.metadata.chain = metadata.chain.or(stored.metadata.chain.take());
+        Ok(())
+    }
+
+    async fn delete_event(
+        &self,
+        event: EventRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        self.authorize(&event.key, secret_key)?;
+        let mut store = self.store();
+        let now = store.tick();
+        let stored = store
+            .events
+            .iter_mut()
+            .find(|e| e.key == event.key && e.event_id == event.event_id && e.deleted_at.is_none())
+            .ok_or_else(|| ApiError::NotFound("No event found".to_string()))?;
+        stored.deleted_at = Some(i64::from(now));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContractMetadata, EventMetadata};
+    use secp256k1_zkp::Secp256k1;
+
+    fn keys(byte: u8) -> (SecretKey, String) {
+        let secret_key = SecretKey::from_slice(&[byte; 32]).expect("should be a valid secret key");
+        let public_key = secret_key.public_key(&Secp256k1::signing_only());
+        (secret_key, public_key.to_string())
+    }
+
+    #[actix_rt::test]
+    async fn test_contracts_round_trip() {
+        let api = MemoryStorageApi::new();
+        let (secret_key, key) = keys(1);
+        let contract = |uuid: &str| NewContract {
+            uuid: uuid.to_string(),
+            state: "offered".to_string(),
+            content: "abc".to_string(),
+            key: key.clone(),
+            metadata: ContractMetadata {
+                collateral: Some(100),
+                ..Default::default()
+            },
+        };
+        api.create_contract(contract("c1"), secret_key)
+            .await
+            .expect("should create contract");
+        api.create_contract(contract("c2"), secret_key)
+            .await
+            .expect("should create contract");
+        let err = api
+            .create_contract(contract("c1"), secret_key)
+            .await
+            .expect_err("uuids are unique per key");
+        assert!(matches!(err, ApiError::Conflict(_)), "{}", err);
+
+        api.update_contract(
+            UpdateContract {
+                uuid: "c1".to_string(),
+                state: Some("signed".to_string()),
+                content: None,
+                key: key.clone(),
+                metadata: ContractMetadata::default(),
+            },
+            secret_key,
+        )
+        .await
+        .expect("should update contract");
+        let signed = api
+            .get_contracts(
+                ContractsRequestParams {
+                    key: key.clone(),
+                    state: Some("signed,confirmed".to_string()),
+                    collateral_min: Some(100),
+                    ..Default::default()
+                },
+                secret_key,
+            )
+            .await
+            .expect("should get contracts");
+        assert_eq!(signed.len(), 1);
+        assert_eq!(signed[0].content, "abc");
+        assert_eq!(signed[0].metadata.collateral, Some(100));
+
+        let c2 = ContractRequestParams {
+            key: key.clone(),
+            uuid: "c2".to_string(),
+        };
+        api.delete_contract(c2.clone(), secret_key)
+            .await
+            .expect("should delete contract");
+        assert!(api
+            .get_contract(c2.clone(), secret_key)
+            .await
+            .expect("should get contract")
+            .is_none());
+        let err = api
+            .delete_contract(c2, secret_key)
+            .await
+            .expect_err("contract is already deleted");
+        assert!(matches!(err, ApiError::NotFound(_)), "{}", err);
+    }
+
+    #[actix_rt::test]
+    async fn test_keys_only_reach_their_own_data() {
+        let api = MemoryStorageApi::new();
+        let (secret_key, key) = keys(1);
+        let (other_secret_key, _) = keys(2);
+        let event = NewEvent {
+            event_id: "e1".to_string(),
+            content: "abc".to_string(),
+            key: key.clone(),
+            metadata: EventMetadata::default(),
+        };
+        let err = api
+            .create_event(event.clone(), other_secret_key)
+            .await
+            .expect_err("should not write for another key");
+        assert!(matches!(err, ApiError::Unauthorized(_)), "{}", err);
+        api.create_event(event, secret_key)
+            .await
+            .expect("should create event");
+
+        let request = EventRequestParams {
+            key,
+            event_id: "e1".to_string(),
+        };
+        let err = api
+            .get_event(request.clone(), other_secret_key)
+            .await
+            .expect_err("should not read for another key");
+        assert!(matches!(err, ApiError::Unauthorized(_)), "{}", err);
+        // clones share what is stored
+        let event = api
+            .clone()
+            .get_event(request, secret_key)
+            .await
+            .expect("should get event");
+        assert_eq!(event.map(|e| e.content), Some("abc".to_string()));
+    }
+}
@@ -1,2 +1,2 @@
 pub(crate) mod async_storage_provider;
-mod utils;
+pub mod utils;
@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use reqwest::{Response, StatusCode};
+
+use crate::ApiError;
+
+/// How [`StorageApiClient`] copes with a storage API that is slow or briefly down.
+///
+/// GETs and updates are retried when the storage API can't be reached, times out or
+/// answers with a 500, 502, 503 or 504, with a fresh nonce and signature for every
+/// attempt. Creates, deletes and imports are never retried, since they could be applied
+/// twice. Requests that keep failing open a circuit breaker shared by the clones of a
+/// client, which then fails requests right away until `cooldown` has passed.
+///
+/// [`StorageApiClient`]: crate::StorageApiClient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one, 0 to never retry.
+    pub max_retries: u32,
+    /// The backoff before the first retry, doubled for every retry after it. A random
+    /// part of up to half of it is left out, so clients don't retry in lockstep.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// The timeout of every attempt. Not enforced on wasm32, where reqwest has none.
+    pub timeout: Duration,
+    /// How many failed requests in a row open the circuit breaker, 0 to never open it.
+    pub failure_threshold: u32,
+    /// How long the open circuit breaker fails requests before letting one through again.
+    pub cooldown: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            timeout: crate::REQWEST_TIMEOUT,
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Neither retries nor opens the circuit breaker, like the client did before it
+    /// had a policy.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            failure_threshold: 0,
+            ..Default::default()
+        }
+    }
+
+    /// The backoff before retry number `retry`, counting from 0.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let mut random = [0u8; 4];
+        // without randomness, back off the full time
+        let jitter = match getrandom::getrandom(&mut random) {
+            Ok(()) => u32::from_le_bytes(random) as f64 / u32::MAX as f64,
+            Err(_) => 0.0,
+        };
+        backoff.mul_f64(1.0 - jitter / 2.0)
+    }
+}
+
+/// Why an attempt to send a request failed: the storage API wasn't reached, or the
+/// request couldn't be built or signed.
+pub(crate) enum SendError {
+    Transport(reqwest::Error),
+    Api(ApiError),
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(e: reqwest::Error) -> Self {
+        SendError::Transport(e)
+    }
+}
+
+impl From<ApiError> for SendError {
+    fn from(e: ApiError) -> Self {
+        SendError::Api(e)
+    }
+}
+
+impl From<SendError> for ApiError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::Transport(e) => e.into(),
+            SendError::Api(e) => e,
+        }
+    }
+}
+
+/// Whether the outcome of an attempt might be different when it is tried again.
+pub(crate) fn is_transient(outcome: &Result<Response, SendError>) -> bool {
+    match outcome {
+        Ok(res) => matches!(
+            res.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(SendError::Transport(e)) => e.is_timeout() || is_connect(e),
+        Err(SendError::Api(_)) => false,
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn is_connect(e: &reqwest::Error) -> bool {
+    e.is_connect()
+}
+
+// fetch doesn't tell why a request failed, a failed request most likely never got an answer
+#[cfg(target_arch = "wasm32")]
+fn is_connect(e: &reqwest::Error) -> bool {
+    e.is_request()
+}
+
+/// Counts failed requests in a row, and fails requests while it is open.
+#[derive(Debug, Default)]
+pub(crate) struct CircuitBreaker {
+    failures: u32,
+    open_until: Option<Duration>,
+}
+
+impl CircuitBreaker {
+    /// Whether a request may be sent. Once the cooldown has passed, requests are let
+    /// through again, and the first one that fails opens the breaker anew.
+    pub(crate) fn allows(&self) -> bool {
+        self.open_until.map_or(true, |until| now() >= until)
+    }
+
+    pub(crate) fn record(&mut self, policy: &RetryPolicy, failed: bool) {
+        if !failed {
+            self.failures = 0;
+            self.open_until = None;
+            return;
+        }
+        self.failures = self.failures.saturating_add(1);
+        if policy.failure_threshold > 0 && self.failures >= policy.failure_threshold {
+            self.open_until = Some(now() + policy.cooldown);
+        }
+    }
+}
+
+pub(crate) fn circuit_open() -> ApiError {
+    ApiError::Transport(
+        "storage API is failing, not sending requests until it has cooled down".to_string(),
+    )
+}
+
+/// The time since the unix epoch, wasm32 has no clock in std.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> Duration {
+    Duration::from_millis(js_sys::Date::now() as u64)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep(duration: Duration) {
+    gloo_timers::future::sleep(duration).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_max() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..Default::default()
+        };
+        for (retry, full) in [(0, 100), (1, 200), (2, 300), (10, 300)] {
+            let backoff = policy.backoff(retry);
+            let full = Duration::from_millis(full);
+            assert!(backoff <= full && backoff >= full / 2, "{:?}", backoff);
+        }
+    }
+
+    #[test]
+    fn test_breaker_opens_after_failures_in_a_row() {
+        let policy = RetryPolicy {
+            failure_threshold: 2,
+            cooldown: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let mut breaker = CircuitBreaker::default();
+        breaker.record(&policy, true);
+        breaker.record(&policy, false);
+        breaker.record(&policy, true);
+        assert!(breaker.allows());
+        breaker.record(&policy, true);
+        assert!(!breaker.allows());
+
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..10 {
+            breaker.record(&RetryPolicy::none(), true);
+        }
+        assert!(breaker.allows());
+    }
+}
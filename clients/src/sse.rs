@@ -0,0 +1,74 @@
+/// Splits a server-sent events byte stream into events.
+///
+/// Only what the storage API sends is supported: `\n` line endings, and `event`,
+/// `data` and `id` fields. Comments (keepalives) are skipped.
+#[derive(Default)]
+pub(crate) struct SseBuffer {
+    buffer: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+impl SseBuffer {
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// The next complete event, if one has been received.
+    pub fn next_event(&mut self) -> Option<SseEvent> {
+        loop {
+            let end = self.buffer.windows(2).position(|w| w == b"\n\n")?;
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let block = String::from_utf8_lossy(&block);
+
+            let mut event = "message".to_string();
+            let mut data: Vec<&str> = vec![];
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("event:") {
+                    event = value.trim_start().to_string();
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data.push(value.strip_prefix(' ').unwrap_or(value));
+                }
+            }
+            if !data.is_empty() {
+                return Some(SseEvent {
+                    event,
+                    data: data.join("\n"),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_split_across_chunks() {
+        let mut buffer = SseBuffer::default();
+        buffer.push(b": keepalive\n\nid: 1\nevent: change\nda");
+        assert_eq!(buffer.next_event(), None);
+
+        buffer.push(b"ta: {\"id\":1}\n\nid: 2\ndata: a\ndata: b\n\n");
+        assert_eq!(
+            buffer.next_event(),
+            Some(SseEvent {
+                event: "change".to_string(),
+                data: "{\"id\":1}".to_string()
+            })
+        );
+        assert_eq!(
+            buffer.next_event(),
+            Some(SseEvent {
+                event: "message".to_string(),
+                data: "a\nb".to_string()
+            })
+        );
+        assert_eq!(buffer.next_event(), None);
+    }
+}
@@ -0,0 +1,155 @@
+use secp256k1_zkp::SecretKey;
+
+use crate::{
+    ApiError, Contract, ContractRequestParams, ContractsRequestParams, Event, EventRequestParams,
+    EventsRequestParams, NewContract, NewEvent, StorageApiClient, UpdateContract, UpdateEvent,
+};
+
+/// The contract and event operations of the storage API, each authorized by the secret
+/// key of the key it reads or writes.
+///
+/// [`StorageApiClient`] implements it over HTTP and [`MemoryStorageApi`] in memory, so
+/// code written against the trait can be tested offline.
+///
+/// [`MemoryStorageApi`]: crate::memory::MemoryStorageApi
+pub trait StorageApi {
+    async fn get_contracts(
+        &self,
+        contract_req: ContractsRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Vec<Contract>, ApiError>;
+
+    async fn get_contract(
+        &self,
+        contract_req: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Option<Contract>, ApiError>;
+
+    async fn create_contract(
+        &self,
+        contract: NewContract,
+        secret_key: SecretKey,
+    ) -> Result<Contract, ApiError>;
+
+    /// Fails if there is no such contract.
+    async fn update_contract(
+        &self,
+        contract: UpdateContract,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError>;
+
+    /// Soft deletes the contract, fails if there is no such contract.
+    async fn delete_contract(
+        &self,
+        contract: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError>;
+
+    async fn get_events(
+        &self,
+        event_req: EventsRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Vec<Event>, ApiError>;
+
+    async fn get_event(
+        &self,
+        event_req: EventRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Option<Event>, ApiError>;
+
+    async fn create_event(&self, event: NewEvent, secret_key: SecretKey)
+        -> Result<Event, ApiError>;
+
+    /// Fails if there is no such event.
+    async fn update_event(&self, event: UpdateEvent, secret_key: SecretKey)
+        -> Result<(), ApiError>;
+
+    /// Soft deletes the event, fails if there is no such event.
+    async fn delete_event(
+        &self,
+        event: EventRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError>;
+}
+
+impl StorageApi for StorageApiClient {
+    async fn get_contracts(
+        &self,
+        contract_req: ContractsRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Vec<Contract>, ApiError> {
+        StorageApiClient::get_contracts(self, contract_req, secret_key).await
+    }
+
+    async fn get_contract(
+        &self,
+        contract_req: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Option<Contract>, ApiError> {
+        StorageApiClient::get_contract(self, contract_req, secret_key).await
+    }
+
+    async fn create_contract(
+        &self,
+        contract: NewContract,
+        secret_key: SecretKey,
+    ) -> Result<Contract, ApiError> {
+        StorageApiClient::create_contract(self, contract, secret_key).await
+    }
+
+    async fn update_contract(
+        &self,
+        contract: UpdateContract,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        StorageApiClient::update_contract(self, contract, secret_key).await
+    }
+
+    async fn delete_contract(
+        &self,
+        contract: ContractRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        StorageApiClient::delete_contract(self, contract, secret_key).await
+    }
+
+    async fn get_events(
+        &self,
+        event_req: EventsRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Vec<Event>, ApiError> {
+        StorageApiClient::get_events(self, event_req, secret_key).await
+    }
+
+    async fn get_event(
+        &self,
+        event_req: EventRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<Option<Event>, ApiError> {
+        StorageApiClient::get_event(self, event_req, secret_key).await
+    }
+
+    async fn create_event(
+        &self,
+        event: NewEvent,
+        secret_key: SecretKey,
+    ) -> Result<Event, ApiError> {
+        StorageApiClient::create_event(self, event, secret_key).await
+    }
+
+    async fn update_event(
+        &self,
+        event: UpdateEvent,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        StorageApiClient::update_event(self, event, secret_key).await
+    }
+
+    async fn delete_event(
+        &self,
+        event: EventRequestParams,
+        secret_key: SecretKey,
+    ) -> Result<(), ApiError> {
+        StorageApiClient::delete_event(self, event, secret_key).await
+    }
+}
@@ -8,6 +8,8 @@ use dlc_manager::contract::ser::Serializable;
 use dlc_manager::contract::{ClosedContract, FailedAcceptContract, FailedSignContract};
 use std::fmt::Write as _;
 
+use crate::ContractMetadata;
+
 pub fn to_storage_error<T>(e: T) -> Error
 where
     T: std::fmt::Display,
@@ -146,6 +148,38 @@ pub fn get_contract_state_str(contract: &Contract) -> String {
     state.to_string()
 }
 
+/// The metadata the storage API indexes `contract` by, as far as its state knows it.
+/// Closed contracts only know their counterparty, the rest stays as stored before.
+pub fn get_contract_metadata(contract: &Contract) -> ContractMetadata {
+    let accepted = match contract {
+        Contract::Accepted(c) => Some(c),
+        Contract::Signed(c) | Contract::Confirmed(c) | Contract::Refunded(c) => {
+            Some(&c.accepted_contract)
+        }
+        Contract::PreClosed(c) => Some(&c.signed_contract.accepted_contract),
+        Contract::FailedSign(c) => Some(&c.accepted_contract),
+        _ => None,
+    };
+    let offered = match contract {
+        Contract::Offered(c) | Contract::Rejected(c) => Some(c),
+        Contract::FailedAccept(c) => Some(&c.offered_contract),
+        _ => accepted.map(|c| &c.offered_contract),
+    };
+    let counterparty = match contract {
+        Contract::Closed(c) => Some(c.counter_party_id),
+        _ => offered.map(|c| c.counter_party),
+    };
+    ContractMetadata {
+        oracle_event_id: offered
+            .and_then(|c| c.contract_info.first())
+            .and_then(|info| info.oracle_announcements.first())
+            .map(|announcement| announcement.oracle_event.event_id.clone()),
+        funding_txid: accepted.map(|c| c.dlc_transactions.fund.txid().to_string()),
+        counterparty: counterparty.map(|key| key.to_string()),
+        collateral: offered.and_then(|c| i64::try_from(c.total_collateral).ok()),
+    }
+}
+
 pub fn get_contract_id_string(contract_id: [u8; 32]) -> String {
     let mut string_id = String::with_capacity(32 * 2 + 2);
     string_id.push_str("0x");
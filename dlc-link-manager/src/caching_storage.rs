@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use crate::dlc_manager::contract::{
+    offered_contract::OfferedContract, signed_contract::SignedContract, Contract, PreClosedContract,
+};
+use crate::dlc_manager::error::Error;
+use crate::AsyncStorage;
+use dlc_manager::ContractId;
+
+/// The deserialized contracts, and how many writes went through the cache, so a load
+/// that raced a write can tell that it is stale.
+#[derive(Default)]
+struct Cache {
+    contracts: Option<HashMap<ContractId, Contract>>,
+    writes: u64,
+}
+
+/// Keeps the contracts of an [`AsyncStorage`] in memory, so that the state queries of
+/// [`Manager::periodic_check`] don't fetch and deserialize every contract again.
+///
+/// All contracts are loaded on the first read and filtered by state from then on.
+/// Writes go to the wrapped storage first and then to the cache, which moves a
+/// contract to its new state. A failed write drops the cache, since it can't tell what
+/// was stored. It has to be the only writer of the wrapped storage, or be
+/// [invalidated](Self::invalidate) when someone else writes, or be made
+/// [uncached](Self::uncached).
+///
+/// [`Manager::periodic_check`]: crate::Manager::periodic_check
+pub struct CachingStorage<S> {
+    inner: S,
+    cache: Mutex<Cache>,
+    enabled: bool,
+}
+
+impl<S: AsyncStorage> CachingStorage<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(Cache::default()),
+            enabled: true,
+        }
+    }
+
+    /// Passes every call through to `inner` without caching anything, for storage that
+    /// other writers share.
+    pub fn uncached(inner: S) -> Self {
+        Self {
+            enabled: false,
+            ..Self::new(inner)
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Drops the cached contracts, the next read loads them again.
+    pub fn invalidate(&self) {
+        let mut cache = self.lock();
+        cache.contracts = None;
+        cache.writes += 1;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache.lock().expect("Failed to lock contract cache")
+    }
+
+    /// The cached contracts `select` picks, after loading them all if they aren't yet,
+    /// or the result of `uncached` if caching is off.
+    async fn contracts_where<T, F>(
+        &self,
+        select: impl Fn(&Contract) -> Option<T>,
+        uncached: impl FnOnce() -> F,
+    ) -> Result<Vec<T>, Error>
+    where
+        F: Future<Output = Result<Vec<T>, Error>>,
+    {
+        if !self.enabled {
+            return uncached().await;
+        }
+        let writes = {
+            let cache = self.lock();
+            if let Some(contracts) = &cache.contracts {
+                return Ok(contracts.values().filter_map(select).collect());
+            }
+            cache.writes
+        };
+        let contracts = self.inner.get_contracts().await?;
+        let selected = contracts.iter().filter_map(&select).collect();
+        let mut cache = self.lock();
+        // a write since the load started may be missing from it
+        if cache.writes == writes {
+            cache.contracts = Some(contracts.into_iter().map(|c| (c.get_id(), c)).collect());
+        }
+        Ok(selected)
+    }
+
+    /// Applies `write` to the cache if it succeeded in the wrapped storage, or drops
+    /// the cache if it didn't.
+    fn write_through(
+        &self,
+        result: Result<(), Error>,
+        write: impl FnOnce(&mut HashMap<ContractId, Contract>),
+    ) -> Result<(), Error> {
+        let mut cache = self.lock();
+        cache.writes += 1;
+        match result {
+            Ok(()) => {
+                if let Some(contracts) = cache.contracts.as_mut() {
+                    write(contracts);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                cache.contracts = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<S: AsyncStorage> AsyncStorage for CachingStorage<S> {
+    async fn get_contract(&self, id: &ContractId) -> Result<Option<Contract>, Error> {
+        if let Some(contracts) = &self.lock().contracts {
+            return Ok(contracts.get(id).cloned());
+        }
+        self.inner.get_contract(id).await
+    }
+
+    async fn get_contracts(&self) -> Result<Vec<Contract>, Error> {
+        self.contracts_where(|c| Some(c.clone()), || self.inner.get_contracts())
+            .await
+    }
+
+    async fn create_contract(&self, contract: &OfferedContract) -> Result<(), Error> {
+        let result = self.inner.create_contract(contract).await;
+        self.write_through(result, |contracts| {
+            contracts.insert(contract.id, Contract::Offered(contract.clone()));
+        })
+    }
+
+    async fn delete_contract(&self, id: &ContractId) -> Result<(), Error> {
+        let result = self.inner.delete_contract(id).await;
+        self.write_through(result, |contracts| {
+            contracts.remove(id);
+        })
+    }
+
+    async fn update_contract(&self, contract: &Contract) -> Result<(), Error> {
+        let result = self.inner.update_contract(contract).await;
+        self.write_through(result, |contracts| {
+            // accepted and signed contracts replace the offer stored under their temporary id
+            if let Contract::Accepted(_) | Contract::Signed(_) = contract {
+                contracts.remove(&contract.get_temporary_id());
+            }
+            contracts.insert(contract.get_id(), contract.clone());
+        })
+    }
+
+    async fn get_contract_offers(&self) -> Result<Vec<OfferedContract>, Error> {
+        self.contracts_where(
+            |contract| match contract {
+                Contract::Offered(c) => Some(c.clone()),
+                _ => None,
+            },
+            || self.inner.get_contract_offers(),
+        )
+        .await
+    }
+
+    async fn get_signed_contracts(&self) -> Result<Vec<SignedContract>, Error> {
+        self.contracts_where(
+            |contract| match contract {
+                Contract::Signed(c) => Some(c.clone()),
+                _ => None,
+            },
+            || self.inner.get_signed_contracts(),
+        )
+        .await
+    }
+
+    async fn get_confirmed_contracts(&self) -> Result<Vec<SignedContract>, Error> {
+        self.contracts_where(
+            |contract| match contract {
+                Contract::Confirmed(c) => Some(c.clone()),
+                _ => None,
+            },
+            || self.inner.get_confirmed_contracts(),
+        )
+        .await
+    }
+
+    async fn get_preclosed_contracts(&self) -> Result<Vec<PreClosedContract>, Error> {
+        self.contracts_where(
+            |contract| match contract {
+                Contract::PreClosed(c) => Some(c.clone()),
+                _ => None,
+            },
+            || self.inner.get_preclosed_contracts(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dlc_manager::contract::accepted_contract::AcceptedContract;
+    use crate::dlc_manager::contract::contract_info::ContractInfo;
+    use crate::dlc_manager::contract::enum_descriptor::EnumDescriptor;
+    use crate::dlc_manager::contract::ContractDescriptor;
+    use bitcoin::{PackedLockTime, Script, Transaction, TxOut};
+    use dlc::{DlcTransactions, PartyParams};
+    use dlc_messages::FundingSignatures;
+    use futures::channel::oneshot;
+    use futures::executor::block_on;
+    use secp256k1_zkp::ecdsa::Signature;
+    use secp256k1_zkp::{PublicKey, Secp256k1, SecretKey};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// An in-memory [`AsyncStorage`] that counts its reads, can fail its writes, and can
+    /// hold a load back until a write went through.
+    #[derive(Default)]
+    struct MemoryStorage {
+        contracts: Mutex<HashMap<ContractId, Contract>>,
+        reads: AtomicUsize,
+        fail_writes: AtomicBool,
+        load_gate: Mutex<Option<oneshot::Receiver<()>>>,
+    }
+
+    impl MemoryStorage {
+        fn reads(&self) -> usize {
+            self.reads.load(Ordering::SeqCst)
+        }
+
+        fn contracts_where<T>(&self, select: impl Fn(&Contract) -> Option<T>) -> Vec<T> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let contracts = self.contracts.lock().expect("should lock the contracts");
+            contracts.values().filter_map(select).collect()
+        }
+
+        fn write(
+            &self,
+            write: impl FnOnce(&mut HashMap<ContractId, Contract>),
+        ) -> Result<(), Error> {
+            if self.fail_writes.load(Ordering::SeqCst) {
+                return Err(Error::StorageError("write failed".to_string()));
+            }
+            write(&mut self.contracts.lock().expect("should lock the contracts"));
+            Ok(())
+        }
+    }
+
+    impl AsyncStorage for MemoryStorage {
+        async fn get_contract(&self, id: &ContractId) -> Result<Option<Contract>, Error> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let contracts = self.contracts.lock().expect("should lock the contracts");
+            Ok(contracts.get(id).cloned())
+        }
+
+        async fn get_contracts(&self) -> Result<Vec<Contract>, Error> {
+            let contracts = self.contracts_where(|c| Some(c.clone()));
+            let gate = self.load_gate.lock().expect("should lock the gate").take();
+            if let Some(gate) = gate {
+                gate.await.expect("the write should open the gate");
+            }
+            Ok(contracts)
+        }
+
+        async fn create_contract(&self, contract: &OfferedContract) -> Result<(), Error> {
+            self.write(|contracts| {
+                contracts.insert(contract.id, Contract::Offered(contract.clone()));
+            })
+        }
+
+        async fn delete_contract(&self, id: &ContractId) -> Result<(), Error> {
+            self.write(|contracts| {
+                contracts.remove(id);
+            })
+        }
+
+        async fn update_contract(&self, contract: &Contract) -> Result<(), Error> {
+            self.write(|contracts| {
+                if let Contract::Accepted(_) | Contract::Signed(_) = contract {
+                    contracts.remove(&contract.get_temporary_id());
+                }
+                contracts.insert(contract.get_id(), contract.clone());
+            })
+        }
+
+        async fn get_contract_offers(&self) -> Result<Vec<OfferedContract>, Error> {
+            Ok(self.contracts_where(|contract| match contract {
+                Contract::Offered(c) => Some(c.clone()),
+                _ => None,
+            }))
+        }
+
+        async fn get_signed_contracts(&self) -> Result<Vec<SignedContract>, Error> {
+            Ok(self.contracts_where(|contract| match contract {
+                Contract::Signed(c) => Some(c.clone()),
+                _ => None,
+            }))
+        }
+
+        async fn get_confirmed_contracts(&self) -> Result<Vec<SignedContract>, Error> {
+            Ok(self.contracts_where(|contract| match contract {
+                Contract::Confirmed(c) => Some(c.clone()),
+                _ => None,
+            }))
+        }
+
+        async fn get_preclosed_contracts(&self) -> Result<Vec<PreClosedContract>, Error> {
+            Ok(self.contracts_where(|contract| match contract {
+                Contract::PreClosed(c) => Some(c.clone()),
+                _ => None,
+            }))
+        }
+    }
+
+    fn public_key() -> PublicKey {
+        let secret = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    fn signature() -> Signature {
+        Signature::from_compact(&[1; 64]).expect("should be a valid signature")
+    }
+
+    fn transaction(output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output,
+        }
+    }
+
+    fn party_params() -> PartyParams {
+        PartyParams {
+            fund_pubkey: public_key(),
+            change_script_pubkey: Script::new(),
+            change_serial_id: 0,
+            payout_script_pubkey: Script::new(),
+            payout_serial_id: 1,
+            inputs: vec![],
+            input_amount: 0,
+            collateral: 50_000,
+        }
+    }
+
+    fn offer(id: u8) -> OfferedContract {
+        OfferedContract {
+            id: [id; 32],
+            is_offer_party: true,
+            contract_info: vec![ContractInfo {
+                contract_descriptor: ContractDescriptor::Enum(EnumDescriptor {
+                    outcome_payouts: vec![],
+                }),
+                oracle_announcements: vec![],
+                threshold: 1,
+            }],
+            counter_party: public_key(),
+            offer_params: party_params(),
+            total_collateral: 100_000,
+            funding_inputs_info: vec![],
+            fund_output_serial_id: 0,
+            fee_rate_per_vb: 1,
+            cet_locktime: 0,
+            refund_locktime: 0,
+        }
+    }
+
+    fn accept(offered_contract: OfferedContract) -> AcceptedContract {
+        let funding_script_pubkey = Script::new();
+        let fund = transaction(vec![TxOut {
+            value: offered_contract.total_collateral,
+            script_pubkey: funding_script_pubkey.to_v0_p2wsh(),
+        }]);
+        AcceptedContract {
+            offered_contract,
+            accept_params: party_params(),
+            funding_inputs: vec![],
+            adaptor_infos: vec![],
+            adaptor_signatures: None,
+            accept_refund_signature: signature(),
+            dlc_transactions: DlcTransactions {
+                fund,
+                cets: vec![],
+                refund: transaction(vec![]),
+                funding_script_pubkey,
+            },
+        }
+    }
+
+    fn sign(accepted_contract: AcceptedContract) -> SignedContract {
+        SignedContract {
+            accepted_contract,
+            adaptor_signatures: None,
+            offer_refund_signature: signature(),
+            funding_signatures: FundingSignatures {
+                funding_signatures: vec![],
+            },
+            channel_id: None,
+        }
+    }
+
+    fn offer_ids(offers: Vec<OfferedContract>) -> Vec<ContractId> {
+        offers.iter().map(|c| c.id).collect()
+    }
+
+    #[test]
+    fn test_reads_hit_the_cache() {
+        block_on(async {
+            let storage = CachingStorage::new(MemoryStorage::default());
+            let offered = offer(1);
+            storage
+                .create_contract(&offered)
+                .await
+                .expect("should create the offer");
+
+            let offers = storage
+                .get_contract_offers()
+                .await
+                .expect("should read the offers");
+            assert_eq!(offer_ids(offers), [offered.id]);
+            assert_eq!(storage.inner().reads(), 1);
+
+            storage
+                .get_contracts()
+                .await
+                .expect("should read the contracts");
+            assert!(storage
+                .get_signed_contracts()
+                .await
+                .expect("should read the signed contracts")
+                .is_empty());
+            assert!(storage
+                .get_contract(&offered.id)
+                .await
+                .expect("should read the contract")
+                .is_some());
+            assert_eq!(storage.inner().reads(), 1);
+        });
+    }
+
+    #[test]
+    fn test_writes_move_cached_contracts() {
+        block_on(async {
+            let storage = CachingStorage::new(MemoryStorage::default());
+            let offered = offer(1);
+            storage
+                .create_contract(&offered)
+                .await
+                .expect("should create the offer");
+            storage
+                .get_contracts()
+                .await
+                .expect("should load the cache");
+
+            let accepted = accept(offered.clone());
+            let id = accepted.get_contract_id();
+            storage
+                .update_contract(&Contract::Accepted(accepted.clone()))
+                .await
+                .expect("should accept the contract");
+            assert!(storage
+                .get_contract_offers()
+                .await
+                .expect("should read the offers")
+                .is_empty());
+            assert!(storage
+                .get_contract(&offered.id)
+                .await
+                .expect("should read the contract")
+                .is_none());
+
+            storage
+                .update_contract(&Contract::Signed(sign(accepted)))
+                .await
+                .expect("should sign the contract");
+            let signed = storage
+                .get_signed_contracts()
+                .await
+                .expect("should read the signed contracts");
+            assert_eq!(
+                signed
+                    .iter()
+                    .map(|c| c.accepted_contract.get_contract_id())
+                    .collect::<Vec<_>>(),
+                [id]
+            );
+
+            storage
+                .delete_contract(&id)
+                .await
+                .expect("should delete the contract");
+            assert!(storage
+                .get_contracts()
+                .await
+                .expect("should read the contracts")
+                .is_empty());
+            assert_eq!(storage.inner().reads(), 1);
+        });
+    }
+
+    #[test]
+    fn test_failed_write_drops_the_cache() {
+        block_on(async {
+            let storage = CachingStorage::new(MemoryStorage::default());
+            let offered = offer(1);
+            storage
+                .create_contract(&offered)
+                .await
+                .expect("should create the offer");
+            storage
+                .get_contracts()
+                .await
+                .expect("should load the cache");
+
+            storage.inner().fail_writes.store(true, Ordering::SeqCst);
+            assert!(storage
+                .update_contract(&Contract::Accepted(accept(offered.clone())))
+                .await
+                .is_err());
+            storage.inner().fail_writes.store(false, Ordering::SeqCst);
+
+            let offers = storage
+                .get_contract_offers()
+                .await
+                .expect("should read the offers");
+            assert_eq!(offer_ids(offers), [offered.id]);
+            assert_eq!(storage.inner().reads(), 2);
+        });
+    }
+
+    #[test]
+    fn test_uncached_reads_pass_through() {
+        block_on(async {
+            let storage = CachingStorage::uncached(MemoryStorage::default());
+            let offered = offer(1);
+            storage
+                .create_contract(&offered)
+                .await
+                .expect("should create the offer");
+
+            for _ in 0..2 {
+                let offers = storage
+                    .get_contract_offers()
+                    .await
+                    .expect("should read the offers");
+                assert_eq!(offer_ids(offers), [offered.id]);
+            }
+            assert!(storage
+                .get_contract(&offered.id)
+                .await
+                .expect("should read the contract")
+                .is_some());
+            assert_eq!(storage.inner().reads(), 3);
+        });
+    }
+
+    #[test]
+    fn test_load_racing_a_write_is_not_cached() {
+        block_on(async {
+            let storage = CachingStorage::new(MemoryStorage::default());
+            let offered = offer(1);
+            storage
+                .create_contract(&offered)
+                .await
+                .expect("should create the offer");
+
+            let (open, gate) = oneshot::channel();
+            *storage
+                .inner()
+                .load_gate
+                .lock()
+                .expect("should lock the gate") = Some(gate);
+            let accepted = Contract::Accepted(accept(offered.clone()));
+            let write = async {
+                let result = storage.update_contract(&accepted).await;
+                open.send(()).expect("the load should wait on the gate");
+                result
+            };
+            // the load reads the offer, then waits until the write accepted it
+            let (loaded, written) = futures::join!(storage.get_contract_offers(), write);
+            written.expect("should accept the contract");
+            assert_eq!(
+                offer_ids(loaded.expect("should read the offers")),
+                [offered.id]
+            );
+
+            assert!(storage
+                .get_contract_offers()
+                .await
+                .expect("should read the offers")
+                .is_empty());
+            assert_eq!(storage.inner().reads(), 2);
+        });
+    }
+}
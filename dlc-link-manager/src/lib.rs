@@ -29,6 +29,10 @@ use std::collections::HashMap;
 use std::ops::Deref;
 use std::string::ToString;
 
+mod caching_storage;
+
+pub use caching_storage::CachingStorage;
+
 /// The number of confirmations required before moving the the confirmed state.
 pub const NB_CONFIRMATIONS: u32 = 6;
 /// The upper bound for the delay refund verification check, 10 years.
@@ -0,0 +1,359 @@
+#![feature(async_fn_in_trait)]
+#![deny(clippy::unwrap_used)]
+
+use std::path::Path;
+
+use dlc_clients::utils::{deserialize_contract, serialize_contract, to_storage_error};
+use dlc_link_manager::AsyncStorage;
+use dlc_manager::contract::offered_contract::OfferedContract;
+use dlc_manager::contract::signed_contract::SignedContract;
+use dlc_manager::contract::{Contract, PreClosedContract};
+use dlc_manager::error::Error;
+use dlc_manager::ContractId;
+
+const CONTRACT_TREE: &str = "contracts";
+
+/// Stores contracts in an embedded sled database instead of the storage API, for router
+/// wallets that run on a single host and for tests.
+///
+/// Contracts are serialized like [`AsyncStorageApiProvider`] stores them, prefixed by
+/// their state, and keyed by their id. The database is accessed from the blocking
+/// thread pool, so the provider has to be used within a tokio runtime.
+///
+/// [`AsyncStorageApiProvider`]: dlc_clients::async_storage_provider::AsyncStorageApiProvider
+pub struct SledStorageProvider {
+    contracts: sled::Tree,
+}
+
+impl SledStorageProvider {
+    /// Opens the database at `path`, creating it if there is none.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_db(sled::open(path).map_err(to_storage_error)?)
+    }
+
+    /// A database that is deleted when it is dropped.
+    pub fn temporary() -> Result<Self, Error> {
+        Self::from_db(
+            sled::Config::new()
+                .temporary(true)
+                .open()
+                .map_err(to_storage_error)?,
+        )
+    }
+
+    fn from_db(db: sled::Db) -> Result<Self, Error> {
+        Ok(Self {
+            contracts: db.open_tree(CONTRACT_TREE).map_err(to_storage_error)?,
+        })
+    }
+
+    /// Runs `op` on the blocking thread pool of the tokio runtime, since sled reads and
+    /// flushes block on disk io and would stall the executor with them.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&sled::Tree) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let contracts = self.contracts.clone();
+        tokio::task::spawn_blocking(move || op(&contracts))
+            .await
+            .map_err(to_storage_error)?
+    }
+
+    /// Applies `batch` atomically and flushes it to disk.
+    async fn apply(&self, batch: sled::Batch) -> Result<(), Error> {
+        self.blocking(move |contracts| {
+            contracts.apply_batch(batch).map_err(to_storage_error)?;
+            contracts.flush().map_err(to_storage_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The stored contracts `select` picks, in the order of their ids.
+    async fn contracts_where<T: Send + 'static>(
+        &self,
+        select: impl Fn(Contract) -> Option<T> + Send + 'static,
+    ) -> Result<Vec<T>, Error> {
+        self.blocking(move |contracts| {
+            let mut selected = vec![];
+            for entry in contracts.iter() {
+                let (_, data) = entry.map_err(to_storage_error)?;
+                if let Some(contract) = select(deserialize_contract(&data.to_vec())?) {
+                    selected.push(contract);
+                }
+            }
+            Ok(selected)
+        })
+        .await
+    }
+}
+
+impl AsyncStorage for SledStorageProvider {
+    async fn get_contract(&self, id: &ContractId) -> Result<Option<Contract>, Error> {
+        let id = *id;
+        self.blocking(
+            move |contracts| match contracts.get(id).map_err(to_storage_error)? {
+                Some(data) => Ok(Some(deserialize_contract(&data.to_vec())?)),
+                None => Ok(None),
+            },
+        )
+        .await
+    }
+
+    async fn get_contracts(&self) -> Result<Vec<Contract>, Error> {
+        self.contracts_where(Some).await
+    }
+
+    async fn create_contract(&self, contract: &OfferedContract) -> Result<(), Error> {
+        let id = contract.id;
+        let data = serialize_contract(&Contract::Offered(contract.clone()))?;
+        self.blocking(move |contracts| {
+            contracts
+                .compare_and_swap(id, None as Option<&[u8]>, Some(data))
+                .map_err(to_storage_error)?
+                .map_err(|_| Error::StorageError("contract already exists".to_string()))?;
+            contracts.flush().map_err(to_storage_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_contract(&self, id: &ContractId) -> Result<(), Error> {
+        let mut batch = sled::Batch::default();
+        batch.remove(id.as_slice());
+        self.apply(batch).await
+    }
+
+    async fn update_contract(&self, contract: &Contract) -> Result<(), Error> {
+        let mut batch = sled::Batch::default();
+        // stored under their temporary id until now, which has to go with the same write
+        if let Contract::Accepted(_) | Contract::Signed(_) = contract {
+            batch.remove(contract.get_temporary_id().as_slice());
+        }
+        batch.insert(contract.get_id().as_slice(), serialize_contract(contract)?);
+        self.apply(batch).await
+    }
+
+    async fn get_contract_offers(&self) -> Result<Vec<OfferedContract>, Error> {
+        self.contracts_where(|contract| match contract {
+            Contract::Offered(c) => Some(c),
+            _ => None,
+        })
+        .await
+    }
+
+    async fn get_signed_contracts(&self) -> Result<Vec<SignedContract>, Error> {
+        self.contracts_where(|contract| match contract {
+            Contract::Signed(c) => Some(c),
+            _ => None,
+        })
+        .await
+    }
+
+    async fn get_confirmed_contracts(&self) -> Result<Vec<SignedContract>, Error> {
+        self.contracts_where(|contract| match contract {
+            Contract::Confirmed(c) => Some(c),
+            _ => None,
+        })
+        .await
+    }
+
+    async fn get_preclosed_contracts(&self) -> Result<Vec<PreClosedContract>, Error> {
+        self.contracts_where(|contract| match contract {
+            Contract::PreClosed(c) => Some(c),
+            _ => None,
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{PackedLockTime, Script, Transaction, TxOut};
+    use dlc::{DlcTransactions, PartyParams};
+    use dlc_manager::contract::accepted_contract::AcceptedContract;
+    use dlc_manager::contract::contract_info::ContractInfo;
+    use dlc_manager::contract::enum_descriptor::EnumDescriptor;
+    use dlc_manager::contract::ContractDescriptor;
+    use dlc_messages::FundingSignatures;
+    use secp256k1_zkp::ecdsa::Signature;
+    use secp256k1_zkp::{PublicKey, Secp256k1, SecretKey};
+
+    fn public_key() -> PublicKey {
+        let secret = SecretKey::from_slice(&[1; 32]).expect("should be a valid secret key");
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    fn signature() -> Signature {
+        Signature::from_compact(&[1; 64]).expect("should be a valid signature")
+    }
+
+    fn transaction(output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output,
+        }
+    }
+
+    fn party_params() -> PartyParams {
+        PartyParams {
+            fund_pubkey: public_key(),
+            change_script_pubkey: Script::new(),
+            change_serial_id: 0,
+            payout_script_pubkey: Script::new(),
+            payout_serial_id: 1,
+            inputs: vec![],
+            input_amount: 0,
+            collateral: 50_000,
+        }
+    }
+
+    fn offer(id: u8) -> OfferedContract {
+        OfferedContract {
+            id: [id; 32],
+            is_offer_party: true,
+            contract_info: vec![ContractInfo {
+                contract_descriptor: ContractDescriptor::Enum(EnumDescriptor {
+                    outcome_payouts: vec![],
+                }),
+                oracle_announcements: vec![],
+                threshold: 1,
+            }],
+            counter_party: public_key(),
+            offer_params: party_params(),
+            total_collateral: 100_000,
+            funding_inputs_info: vec![],
+            fund_output_serial_id: 0,
+            fee_rate_per_vb: 1,
+            cet_locktime: 0,
+            refund_locktime: 0,
+        }
+    }
+
+    fn accept(offered_contract: OfferedContract) -> AcceptedContract {
+        let funding_script_pubkey = Script::new();
+        let fund = transaction(vec![TxOut {
+            value: offered_contract.total_collateral,
+            script_pubkey: funding_script_pubkey.to_v0_p2wsh(),
+        }]);
+        AcceptedContract {
+            offered_contract,
+            accept_params: party_params(),
+            funding_inputs: vec![],
+            adaptor_infos: vec![],
+            adaptor_signatures: None,
+            accept_refund_signature: signature(),
+            dlc_transactions: DlcTransactions {
+                fund,
+                cets: vec![],
+                refund: transaction(vec![]),
+                funding_script_pubkey,
+            },
+        }
+    }
+
+    fn sign(accepted_contract: AcceptedContract) -> SignedContract {
+        SignedContract {
+            accepted_contract,
+            adaptor_signatures: None,
+            offer_refund_signature: signature(),
+            funding_signatures: FundingSignatures {
+                funding_signatures: vec![],
+            },
+            channel_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_store() {
+        let store = SledStorageProvider::temporary().expect("should open a temporary database");
+        assert!(store
+            .get_contract(&[1; 32])
+            .await
+            .expect("should read the store")
+            .is_none());
+        assert!(store
+            .get_contracts()
+            .await
+            .expect("should read the store")
+            .is_empty());
+        store
+            .delete_contract(&[1; 32])
+            .await
+            .expect("deleting a missing contract is a no-op");
+    }
+
+    #[tokio::test]
+    async fn test_contract_round_trip() {
+        let store = SledStorageProvider::temporary().expect("should open a temporary database");
+        let offered = offer(1);
+        store
+            .create_contract(&offered)
+            .await
+            .expect("should create the offer");
+        assert!(store.create_contract(&offered).await.is_err());
+
+        let offers = store
+            .get_contract_offers()
+            .await
+            .expect("should read the offers");
+        assert_eq!(
+            offers.iter().map(|c| c.id).collect::<Vec<_>>(),
+            [offered.id]
+        );
+        assert!(store
+            .get_signed_contracts()
+            .await
+            .expect("should read the signed contracts")
+            .is_empty());
+
+        let accepted = accept(offered.clone());
+        let id = accepted.get_contract_id();
+        assert_ne!(id, offered.id);
+        store
+            .update_contract(&Contract::Accepted(accepted.clone()))
+            .await
+            .expect("should accept the contract");
+        assert!(store
+            .get_contract(&offered.id)
+            .await
+            .expect("should read the store")
+            .is_none());
+        assert!(matches!(
+            store
+                .get_contract(&id)
+                .await
+                .expect("should read the store"),
+            Some(Contract::Accepted(_))
+        ));
+        assert!(store
+            .get_contract_offers()
+            .await
+            .expect("should read the offers")
+            .is_empty());
+
+        store
+            .update_contract(&Contract::Signed(sign(accepted)))
+            .await
+            .expect("should sign the contract");
+        let signed = store
+            .get_signed_contracts()
+            .await
+            .expect("should read the signed contracts");
+        assert_eq!(
+            signed
+                .iter()
+                .map(|c| c.accepted_contract.get_contract_id())
+                .collect::<Vec<_>>(),
+            [id]
+        );
+        let contracts = store.get_contracts().await.expect("should read the store");
+        assert_eq!(
+            contracts.iter().map(Contract::get_id).collect::<Vec<_>>(),
+            [id]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Simple throughput test against a running storage API.
+//!
+//! Logs in with a random key, seeds a few contracts for it, then fires
+//! `LOAD_TEST_REQUESTS` GET /contracts requests with `LOAD_TEST_CONCURRENCY` in flight
+//! and reports requests per second. Requests carry the session token of the login, so
+//! it also runs with `allow_legacy_auth` off, but not with `require_registration` on.
+//!
+//! The numbers are dominated by database round trips, so run it against a Postgres with
+//! realistic latency and compare runs on the same host rather than absolute numbers.
+//!
+//! e.g.: STORAGE_API_URL=http://localhost:8100 cargo run --release --example load_test
+use std::env;
+use std::time::Instant;
+
+use futures_util::stream::{self, StreamExt};
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::rand::rngs::OsRng;
+use secp256k1::{Message, Secp256k1};
+use serde_json::{json, Value};
+
+fn env_or(name: &str, default: usize) -> usize {
+    env::var(name)
+        .map(|v| v.parse().expect("should parse load test env var to usize"))
+        .unwrap_or(default)
+}
+
+#[tokio::main]
+async fn main() {
+    let host = env::var("STORAGE_API_URL").unwrap_or("http://localhost:8100".to_string());
+    let total = env_or("LOAD_TEST_REQUESTS", 2000);
+    let concurrency = env_or("LOAD_TEST_CONCURRENCY", 100);
+    let seed = env_or("LOAD_TEST_SEED_CONTRACTS", 20);
+
+    let client = reqwest::Client::new();
+    let secp = Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
+    let key = public_key.to_string();
+
+    // sign a login like the storage client does, over the method, path, nonce and message
+    let nonce = client
+        .get(format!("{}/request_nonce", host))
+        .send()
+        .await
+        .expect("should request a nonce")
+        .text()
+        .await
+        .expect("should read the nonce");
+    let message = json!({ "nonce": nonce });
+    let payload = format!("POST\n/login\n{}\n{}", nonce, message);
+    let digest = Message::from(sha256::Hash::hash(payload.as_bytes()));
+    let session: Value = client
+        .post(format!("{}/login", host))
+        .header("authorization", &nonce)
+        .json(&json!({
+            "message": message,
+            "public_key": key,
+            "signature": secp.sign_ecdsa(&digest, &secret_key).to_string(),
+        }))
+        .send()
+        .await
+        .expect("should log in")
+        .error_for_status()
+        .expect("login should succeed")
+        .json()
+        .await
+        .expect("should read the session");
+    let bearer = format!(
+        "Bearer {}",
+        session["token"]
+            .as_str()
+            .expect("session should have a token")
+    );
+
+    for i in 0..seed {
+        client
+            .post(format!("{}/contracts", host))
+            .header("authorization", &bearer)
+            .json(&json!({
+                "uuid": format!("{}-{}", key, i),
+                "state": "signed",
+                "content": "Y29udGVudA==",
+                "key": key,
+            }))
+            .send()
+            .await
+            .expect("should seed contract")
+            .error_for_status()
+            .expect("seeding contract should succeed");
+    }
+
+    let started = Instant::now();
+    let results = stream::iter(0..total)
+        .map(|_| {
+            let client = client.clone();
+            let uri = format!("{}/contracts", host);
+            let key = key.clone();
+            let bearer = bearer.clone();
+            async move {
+                client
+                    .get(uri)
+                    .header("authorization", bearer)
+                    .query(&[("key", key)])
+                    .send()
+                    .await
+                    .map(|res| res.status().is_success())
+                    .unwrap_or(false)
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect::<Vec<bool>>()
+        .await;
+    let elapsed = started.elapsed();
+
+    let failed = results.iter().filter(|ok| !**ok).count();
+    println!(
+        "{} requests ({} failed) with concurrency {} in {:.2?}: {:.0} req/s",
+        total,
+        failed,
+        concurrency,
+        elapsed,
+        total as f64 / elapsed.as_secs_f64()
+    );
+
+    client
+        .delete(format!("{}/contracts/{}?confirm=true", host, key))
+        .header("authorization", &bearer)
+        .json(&json!({ "key": key }))
+        .send()
+        .await
+        .expect("should clean up seeded contracts");
+}
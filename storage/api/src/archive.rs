@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+use crate::openapi::ErrorBody;
+use crate::verify_sigs::{verify_signature, SignatureScheme, SignedBy};
+use actix_web::web;
+use actix_web::web::{Data, Json};
+use actix_web::{get, post, HttpMessage, HttpRequest, HttpResponse};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use dlc_storage_common::models::{
+    Archive, ExportRequestParams, ImportSummary, SignedArchive, ARCHIVE_VERSION,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use secp256k1::rand;
+use serde_json::json;
+
+/// How far ahead of this instance's clock the exporting instance's may be.
+const CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+/// How long after its export an archive can be imported.
+pub struct Imports {
+    pub max_age: Option<Duration>,
+}
+
+impl Imports {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_age: (config.max_archive_age_secs > 0)
+                .then(|| Duration::from_secs(config.max_archive_age_secs)),
+        }
+    }
+
+    /// Fails if the archive was exported longer than `max_age` ago, or claims to be
+    /// exported in the future.
+    fn check_age(&self, archive: &Archive) -> Result<(), ApiError> {
+        let now = dlc_storage_common::now();
+        if archive.exported_at > now + CLOCK_SKEW.as_secs() as i64 {
+            return Err(ApiError::Forbidden(
+                "archive was exported in the future".to_string(),
+            ));
+        }
+        match self.max_age {
+            Some(max_age) if now - archive.exported_at > max_age.as_secs() as i64 => {
+                Err(ApiError::Forbidden(format!(
+                    "archive is older than {}s, export it again",
+                    max_age.as_secs()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// All contracts and events of a key, to be signed by the key and kept as a backup.
+/// Only the key itself can export them, with a signed request or a session.
+#[utoipa::path(
+    tag = "archive",
+    params(ExportRequestParams),
+    responses(
+        (status = 200, description = "Everything stored for the key", body = Archive),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[get("/export")]
+pub async fn export_key(
+    req: HttpRequest,
+    pools: Data<Pools>,
+    export_params: web::Query<ExportRequestParams>,
+) -> Result<HttpResponse, ApiError> {
+    let key = export_params.into_inner().key;
+    SignedBy::require(&req, &key)?;
+    let ckey = key.clone();
+    let nonce = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let archive = db::read(pools, "export_key", &key, move |conn| {
+        dlc_storage_reader::export_key(conn, &ckey, nonce)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(archive))
+}
+
+/// Restores a signed archive from /export. Only archives signed by their own key are
+/// accepted, in the signature scheme of the request, and only from a request signed by
+/// that key too. Every archive can be imported once, and only until `max_archive_age_secs`
+/// after its export, so a leaked archive can't roll the key back later.
+#[utoipa::path(
+    tag = "archive",
+    responses(
+        (status = 200, description = "What the import changed", body = ImportSummary),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[post("/import")]
+pub async fn import_archive(
+    req: HttpRequest,
+    pools: Data<Pools>,
+    imports: Data<Imports>,
+    signed_archive: Json<SignedArchive>,
+) -> Result<HttpResponse, ApiError> {
+    let SignedArchive { archive, signature } = signed_archive.into_inner();
+    if archive.version != ARCHIVE_VERSION {
+        return Err(ApiError::Validation(format!(
+            "unsupported archive version {}, expected {}",
+            archive.version, ARCHIVE_VERSION
+        )));
+    }
+    match req.extensions().get::<SignedBy>() {
+        Some(SignedBy(signer)) if *signer == archive.key => {}
+        _ => {
+            return Err(ApiError::Forbidden(
+                "archives can only be imported by their own key".to_string(),
+            ))
+        }
+    }
+    imports.check_age(&archive)?;
+    let scheme = SignatureScheme::from_headers(req.headers())?;
+    if verify_signature(
+        scheme,
+        signature,
+        archive.key.clone(),
+        &json!(archive).to_string(),
+    )
+    .is_err()
+    {
+        return Err(ApiError::Auth(
+            "archive is not signed by its key".to_string(),
+        ));
+    }
+    let summary = db::write(pools, "import_archive", archive.key.clone(), move |conn| {
+        dlc_storage_writer::import_archive(conn, &archive)
+    })
+    .await
+    .map_err(|e| match e {
+        // the nonce is recorded first, nothing else in the import can be a duplicate
+        ApiError::Database(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            ApiError::Forbidden("archive was already imported".to_string())
+        }
+        e => e,
+    })?;
+    Ok(HttpResponse::Ok().json(summary))
+}
@@ -0,0 +1,52 @@
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+use crate::openapi::ErrorBody;
+use actix_web::web::{Data, Json};
+use actix_web::{post, HttpResponse};
+use dlc_storage_common::models::{BatchRequest, BatchResponse};
+
+/// Past this many operations a batch is rejected, so one request can't hold a
+/// transaction open for long.
+pub const MAX_BATCH_OPERATIONS: usize = 1000;
+
+/// Applies a list of contract and event writes of one key in one transaction, signed
+/// once. Either every operation is applied and their results returned in order, or
+/// none is and the error names the operation that failed.
+#[utoipa::path(
+    tag = "batch",
+    responses(
+        (status = 200, description = "Every operation was applied", body = BatchResponse),
+        (status = "4XX", description = "Nothing was applied, `index` is the operation that failed", body = ErrorBody)
+    )
+)]
+#[post("/batch")]
+pub async fn apply_batch(
+    pools: Data<Pools>,
+    batch: Json<BatchRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let BatchRequest { key, operations } = batch.into_inner();
+    if operations.is_empty() || operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(ApiError::Validation(format!(
+            "a batch has to have 1 to {} operations, not {}",
+            MAX_BATCH_OPERATIONS,
+            operations.len()
+        )));
+    }
+    if let Some(index) = operations.iter().position(|op| op.key() != key) {
+        return Err(ApiError::Batch(
+            index,
+            Box::new(ApiError::Validation(
+                "operation is for another key than the batch".to_string(),
+            )),
+        ));
+    }
+    let results = db::write(pools, "apply_batch", key, move |conn| {
+        Ok(dlc_storage_writer::apply_batch(conn, operations))
+    })
+    .await?
+    .map_err(|failure| match failure.index {
+        Some(index) => ApiError::Batch(index, Box::new(failure.error.into())),
+        None => failure.error.into(),
+    })?;
+    Ok(HttpResponse::Ok().json(BatchResponse { results }))
+}
@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+use crate::openapi::ErrorBody;
+use crate::verify_sigs::SignedBy;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web::{self, Bytes, Data};
+use actix_web::{get, HttpRequest, HttpResponse};
+use dlc_storage_common::models::{Change, ChangeRequestParams};
+use futures_util::stream;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::timeout;
+
+/// Idle feeds send a comment this often, so proxies keep the connection open. It is
+/// also how often a feed looks for writes made through other API instances.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+const PAGE_SIZE: i64 = 100;
+
+/// Server-sent events stream of the changes to a key's contracts and events.
+///
+/// Every change is sent as an `event: change` with its cursor as the event `id`.
+/// Clients resume after reconnecting by passing the last id they saw as `after`
+/// (or as the standard `Last-Event-ID` header, which `EventSource` sends itself).
+/// Only the key itself can subscribe, with a signed request or a session.
+#[utoipa::path(
+    tag = "changes",
+    params(
+        ChangeRequestParams,
+        ("last-event-id" = Option<i32>, Header, description = "Resume after this cursor, overrides `after`"),
+    ),
+    responses(
+        (status = 200, description = "Server-sent events, one `change` event per change", body = Change, content_type = "text/event-stream"),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[get("/changes")]
+pub async fn get_changes(
+    req: HttpRequest,
+    pools: Data<Pools>,
+    change_params: web::Query<ChangeRequestParams>,
+) -> Result<HttpResponse, ApiError> {
+    let change_params = change_params.into_inner();
+    SignedBy::require(&req, &change_params.key)?;
+    let last_event_id = req
+        .headers()
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok());
+    let feed = Feed {
+        written_keys: pools.subscribe(),
+        pools,
+        key: change_params.key,
+        after: last_event_id.or(change_params.after).unwrap_or(0),
+        pending: VecDeque::new(),
+        notified: false,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(stream::unfold(feed, Feed::next)))
+}
+
+struct Feed {
+    pools: Data<Pools>,
+    written_keys: broadcast::Receiver<String>,
+    key: String,
+    after: i32,
+    pending: VecDeque<Change>,
+    /// Woken by a write through this instance, which the replica may not have yet.
+    notified: bool,
+}
+
+impl Feed {
+    async fn next(mut self) -> Option<(Result<Bytes, ApiError>, Self)> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                self.after = change.id;
+                return Some((Ok(sse_event(&change)), self));
+            }
+
+            let (key, after) = (self.key.clone(), self.after);
+            let query =
+                move |conn: &mut _| dlc_storage_reader::get_changes(conn, &key, after, PAGE_SIZE);
+            let changes = if self.notified {
+                db::read_primary(self.pools.clone(), "get_changes", query).await
+            } else {
+                db::read(self.pools.clone(), "get_changes", &self.key, query).await
+            };
+            match changes {
+                Ok(changes) if !changes.is_empty() => {
+                    self.pending.extend(changes);
+                    continue;
+                }
+                Ok(_) => {}
+                // ends the stream, the client resumes from its last id
+                Err(e) => return Some((Err(e), self)),
+            }
+
+            // caught up, until the next notification the reader is recent enough
+            self.notified = false;
+            match timeout(KEEPALIVE, self.written()).await {
+                Ok(true) => {
+                    self.notified = true;
+                    continue;
+                }
+                Ok(false) => return None,
+                Err(_) => return Some((Ok(Bytes::from_static(b": keepalive\n\n")), self)),
+            }
+        }
+    }
+
+    /// Waits for a write to this feed's key, false once the server shuts down.
+    async fn written(&mut self) -> bool {
+        loop {
+            match self.written_keys.recv().await {
+                Ok(key) if key == self.key => return true,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => return true,
+                Err(RecvError::Closed) => return false,
+            }
+        }
+    }
+}
+
+fn sse_event(change: &Change) -> Bytes {
+    Bytes::from(format!(
+        "id: {}\nevent: change\ndata: {}\n\n",
+        change.id,
+        json!(change)
+    ))
+}
@@ -1,91 +1,166 @@
-use crate::DbPool;
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+use crate::openapi::{EffectedNum, ErrorBody};
 use actix_web::web;
-use actix_web::web::{Data, Json, Path};
-use actix_web::{delete, get, post, put, HttpResponse, Responder};
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put, HttpResponse};
 use dlc_storage_common::models::{
-    ContractRequestParams, DeleteContract, NewContract, UpdateContract,
+    BulkDeleteParams, Contract, ContractRequestParams, DeleteContract, NewContract, UpdateContract,
 };
-use log::{debug, warn};
+use log::debug;
 use serde_json::json;
 
+#[utoipa::path(
+    tag = "contracts",
+    params(ContractRequestParams),
+    responses(
+        (status = 200, description = "The contracts matching the filters", body = Vec<Contract>),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[get("/contracts")]
 pub async fn get_contracts(
-    pool: Data<DbPool>,
+    pools: Data<Pools>,
     contract_params: web::Query<ContractRequestParams>,
-) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    match dlc_storage_reader::get_contracts(&mut conn, contract_params.into_inner()) {
-        Ok(contracts) => HttpResponse::Ok().json(contracts),
-        Err(e) => {
-            warn!("Error getting contracts: {:?}", e);
-            HttpResponse::BadRequest().body(e.to_string())
-        }
-    }
+) -> Result<HttpResponse, ApiError> {
+    let contract_params = contract_params.into_inner();
+    let key = contract_params.key.clone();
+    let contracts = db::read(pools, "get_contracts", &key, move |conn| {
+        dlc_storage_reader::get_contracts(conn, contract_params)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(contracts))
 }
 
+#[utoipa::path(
+    tag = "contracts",
+    responses(
+        (status = 200, description = "The created contract", body = Contract),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[post("/contracts")]
 pub async fn create_contract(
-    pool: Data<DbPool>,
+    pools: Data<Pools>,
     contract_params: Json<NewContract>,
-) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    match dlc_storage_writer::create_contract(&mut conn, contract_params.into_inner()) {
-        Ok(contract) => {
-            debug!("Created contract: {:?}", contract.uuid);
-            HttpResponse::Ok().json(contract)
-        }
-        Err(e) => {
-            warn!("Error creating contract: {:?}", e);
-            HttpResponse::BadRequest().body(e.to_string())
-        }
-    }
+) -> Result<HttpResponse, ApiError> {
+    let contract_params = contract_params.into_inner();
+    let contract = db::write(
+        pools,
+        "create_contract",
+        contract_params.key.clone(),
+        move |conn| dlc_storage_writer::create_contract(conn, contract_params),
+    )
+    .await?;
+    debug!("Created contract: {:?}", contract.uuid);
+    Ok(HttpResponse::Ok().json(contract))
 }
 
+#[utoipa::path(
+    tag = "contracts",
+    responses(
+        (status = 200, description = "One contract updated", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[put("/contracts")]
 pub async fn update_contract(
-    pool: Data<DbPool>,
+    pools: Data<Pools>,
     contract_params: Json<UpdateContract>,
-) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    let num_updated =
-        match dlc_storage_writer::update_contract(&mut conn, contract_params.into_inner()) {
-            Ok(num_updated) => num_updated,
-            Err(e) => {
-                warn!("Error updating contract: {:?}", e);
-                return HttpResponse::BadRequest().body(e.to_string());
-            }
-        };
+) -> Result<HttpResponse, ApiError> {
+    let contract_params = contract_params.into_inner();
+    let num_updated = db::write(
+        pools,
+        "update_contract",
+        contract_params.key.clone(),
+        move |conn| dlc_storage_writer::update_contract(conn, contract_params),
+    )
+    .await?;
     match num_updated {
-        0 => HttpResponse::NotFound().body("No contract found"),
-        _ => HttpResponse::Ok().json(json!({ "effected_num": num_updated })),
+        0 => Err(ApiError::NotFound("No contract found".to_string())),
+        _ => Ok(HttpResponse::Ok().json(json!({ "effected_num": num_updated }))),
     }
 }
 
+#[utoipa::path(
+    tag = "contracts",
+    responses(
+        (status = 200, description = "The contract is restored", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[post("/contract/restore")]
+pub async fn restore_contract(
+    pools: Data<Pools>,
+    contract_params: Json<DeleteContract>,
+) -> Result<HttpResponse, ApiError> {
+    let contract_params = contract_params.into_inner();
+    let num_restored = db::write(
+        pools,
+        "restore_contract",
+        contract_params.key.clone(),
+        move |conn| dlc_storage_writer::restore_contract(conn, contract_params),
+    )
+    .await?;
+    match num_restored {
+        0 => Err(ApiError::NotFound("No deleted contract found".to_string())),
+        _ => Ok(HttpResponse::Ok().json(json!({ "effected_num": num_restored }))),
+    }
+}
+
+#[utoipa::path(
+    tag = "contracts",
+    responses(
+        (status = 200, description = "The contract is soft deleted", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[delete("/contract")]
 pub async fn delete_contract(
-    pool: Data<DbPool>,
+    pools: Data<Pools>,
     contract_params: Json<DeleteContract>,
-) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    let num_deleted =
-        match dlc_storage_writer::delete_contract(&mut conn, contract_params.into_inner()) {
-            Ok(num_deleted) => num_deleted,
-            Err(e) => {
-                warn!("Error deleting contract: {:?}", e);
-                return HttpResponse::BadRequest().body(e.to_string());
-            }
-        };
+) -> Result<HttpResponse, ApiError> {
+    let contract_params = contract_params.into_inner();
+    let num_deleted = db::write(
+        pools,
+        "delete_contract",
+        contract_params.key.clone(),
+        move |conn| dlc_storage_writer::delete_contract(conn, contract_params),
+    )
+    .await?;
     match num_deleted {
-        0 => HttpResponse::NotFound().body("No contract found"),
-        _ => HttpResponse::Ok().json(json!({ "effected_num": num_deleted })),
+        0 => Err(ApiError::NotFound("No contract found".to_string())),
+        _ => Ok(HttpResponse::Ok().json(json!({ "effected_num": num_deleted }))),
     }
 }
 
-//remove this?
+/// Soft deletes every contract of a key, only with `confirm=true`.
+#[utoipa::path(
+    tag = "contracts",
+    params(
+        BulkDeleteParams,
+        ("ckey" = String, Path, description = "The public key whose contracts to delete"),
+    ),
+    responses(
+        (status = 200, description = "The contracts of the key are soft deleted", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[delete("/contracts/{ckey}")]
-pub async fn delete_contracts(pool: Data<DbPool>, ckey: Path<String>) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    let num_deleted = dlc_storage_writer::delete_all_contracts(&mut conn, &ckey)
-        .expect("couldn't delete contracts from db");
-    HttpResponse::Ok().json(json!({ "effected_num": num_deleted }))
+pub async fn delete_contracts(
+    pools: Data<Pools>,
+    ckey: Path<String>,
+    params: Query<BulkDeleteParams>,
+) -> Result<HttpResponse, ApiError> {
+    if params.confirm != Some(true) {
+        return Err(ApiError::Validation(
+            "deleting all contracts of a key needs confirm=true".to_string(),
+        ));
+    }
+    let ckey = ckey.into_inner();
+    let num_deleted = db::write(pools, "delete_all_contracts", ckey.clone(), move |conn| {
+        dlc_storage_writer::delete_all_contracts(conn, &ckey)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "effected_num": num_deleted })))
 }
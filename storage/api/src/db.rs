@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::web::{self, Data};
+use diesel::r2d2;
+use dlc_storage_common::{DbConnection, DbConnectionManager};
+use prometheus::{HistogramOpts, HistogramVec, Registry};
+use tokio::sync::broadcast;
+
+use crate::config::Config;
+use crate::error::ApiError;
+
+pub type DbPool = r2d2::Pool<DbConnectionManager>;
+
+/// Subscribers that fall further behind than this just re-read their feed.
+const WRITTEN_KEYS_CAPACITY: usize = 256;
+
+/// Builds a connection pool.
+///
+/// `db_pool_max_size` caps the number of open connections (and so the number of
+/// queries that can run at once), `db_pool_timeout_secs` caps how long a request
+/// waits for a free connection before giving up. The backend (Postgres or SQLite)
+/// follows from the scheme of `database_url`.
+fn build_pool(config: &Config, database_url: &str) -> DbPool {
+    let manager = DbConnectionManager::new(database_url);
+    r2d2::Pool::builder()
+        .max_size(config.db_pool_max_size)
+        .connection_timeout(Duration::from_secs(config.db_pool_timeout_secs))
+        .build(manager)
+        .expect("Failed to create pool.")
+}
+
+/// The writer pool, and the reader pool GETs are served from.
+///
+/// Replicas lag behind the primary, so for `read_your_writes` after a write, reads
+/// for the same key still go to the writer. Every write is also announced to the
+/// change feed subscribers of its key, and every query timed by its operation.
+pub struct Pools {
+    reader: DbPool,
+    writer: DbPool,
+    has_replica: bool,
+    read_your_writes: Duration,
+    recent_writes: Mutex<HashMap<String, Instant>>,
+    written_keys: broadcast::Sender<String>,
+    query_duration: HistogramVec,
+}
+
+impl Pools {
+    pub fn new(reader: DbPool, writer: DbPool, read_your_writes: Duration) -> Self {
+        Self {
+            reader,
+            writer,
+            has_replica: true,
+            read_your_writes,
+            recent_writes: Mutex::new(HashMap::new()),
+            written_keys: broadcast::channel(WRITTEN_KEYS_CAPACITY).0,
+            query_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "db_query_duration_seconds",
+                    "Time taken by database queries, including waiting for a connection",
+                ),
+                &["operation"],
+            )
+            .expect("should create db_query_duration_seconds histogram"),
+        }
+    }
+
+    /// Reads and writes share one pool, e.g. when no read replica is configured.
+    pub fn single(pool: DbPool) -> Self {
+        Self {
+            has_replica: false,
+            ..Self::new(pool.clone(), pool, Duration::ZERO)
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let writer = build_pool(config, &config.database_url);
+        match &config.read_database_url {
+            Some(url) => Self::new(
+                build_pool(config, url),
+                writer,
+                Duration::from_secs(config.read_your_writes_secs),
+            ),
+            None => Self::single(writer),
+        }
+    }
+
+    pub fn register(&self, registry: &Registry) {
+        registry
+            .register(Box::new(self.query_duration.clone()))
+            .expect("should register db_query_duration_seconds histogram");
+    }
+
+    pub fn writer(&self) -> &DbPool {
+        &self.writer
+    }
+
+    /// The reader pool, unless reads share the writer pool.
+    pub fn replica(&self) -> Option<&DbPool> {
+        self.has_replica.then_some(&self.reader)
+    }
+
+    /// Receives the key of every write made through this instance.
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.written_keys.subscribe()
+    }
+
+    fn reader_for(&self, key: &str) -> &DbPool {
+        let recent_writes = self
+            .recent_writes
+            .lock()
+            .expect("Failed to lock recent writes");
+        match recent_writes.get(key) {
+            Some(written_at) if written_at.elapsed() < self.read_your_writes => &self.writer,
+            _ => &self.reader,
+        }
+    }
+
+    fn wrote(&self, key: String) {
+        if !self.read_your_writes.is_zero() {
+            let window = self.read_your_writes;
+            let mut recent_writes = self
+                .recent_writes
+                .lock()
+                .expect("Failed to lock recent writes");
+            recent_writes.retain(|_, written_at| written_at.elapsed() < window);
+            recent_writes.insert(key.clone(), Instant::now());
+        }
+        // only fails if nobody is subscribed
+        let _ = self.written_keys.send(key);
+    }
+}
+
+/// Runs a query for `key` on the reader pool, or on the writer if `key` was written
+/// within the read-your-writes window. `operation` names the query in the metrics.
+pub async fn read<F, T>(
+    pools: Data<Pools>,
+    operation: &'static str,
+    key: &str,
+    query: F,
+) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, diesel::result::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pools.reader_for(key).clone();
+    run(&pools, pool, operation, query).await
+}
+
+/// Runs a query on the writer pool, for reads that must see a write which the replica
+/// may not have replayed yet.
+pub async fn read_primary<F, T>(
+    pools: Data<Pools>,
+    operation: &'static str,
+    query: F,
+) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, diesel::result::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    run(&pools, pools.writer.clone(), operation, query).await
+}
+
+/// Runs a mutation of `key` on the writer pool.
+pub async fn write<F, T>(
+    pools: Data<Pools>,
+    operation: &'static str,
+    key: String,
+    query: F,
+) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, diesel::result::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let result = run(&pools, pools.writer.clone(), operation, query).await?;
+    pools.wrote(key);
+    Ok(result)
+}
+
+/// Runs a mutation that may touch any key on the writer pool, announcing each of the
+/// `keys` it reports having written.
+pub async fn write_keys<F, T>(
+    pools: Data<Pools>,
+    operation: &'static str,
+    query: F,
+    keys: fn(&T) -> &[String],
+) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, diesel::result::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let result = run(&pools, pools.writer.clone(), operation, query).await?;
+    for key in keys(&result) {
+        pools.wrote(key.clone());
+    }
+    Ok(result)
+}
+
+/// Runs a synchronous diesel query on actix's blocking thread pool, so that
+/// waiting on the pool or on the database never stalls an actix worker.
+async fn run<F, T>(
+    pools: &Pools,
+    pool: DbPool,
+    operation: &'static str,
+    query: F,
+) -> Result<T, ApiError>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, diesel::result::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let timer = pools
+        .query_duration
+        .with_label_values(&[operation])
+        .start_timer();
+    let result = web::block(move || {
+        let mut conn = pool.get()?;
+        Ok(query(&mut conn)?)
+    })
+    .await?;
+    timer.observe_duration();
+    result
+}
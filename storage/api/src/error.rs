@@ -0,0 +1,300 @@
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::error::BlockingError;
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use diesel::r2d2::PoolError;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use log::error;
+use serde_json::json;
+
+/// Every failure the storage API can answer with.
+///
+/// Each variant maps to a status code and a machine readable `code`, and is rendered as
+/// `{"error": {"code": "...", "message": "..."}}`.
+#[derive(Debug)]
+pub enum ApiError {
+    /// A diesel query failed.
+    Database(DieselError),
+    /// No connection could be acquired from the pool within the configured timeout.
+    Pool(PoolError),
+    /// The blocking thread pool could not run the query (e.g. it was shut down).
+    Blocking(BlockingError),
+    /// Missing or invalid signature or nonce.
+    Auth(String),
+    /// The session token is unknown or has expired, the client has to log in again.
+    SessionExpired(String),
+    /// Too many requests, the client can try again after the given time.
+    RateLimited(Duration),
+    /// The signer is authenticated but not allowed to do this, e.g. an unregistered key.
+    Forbidden(String),
+    /// The write would take the key past its storage quota.
+    QuotaExceeded(String),
+    /// The request could not be parsed or is missing required fields.
+    Validation(String),
+    /// The addressed resource does not exist.
+    NotFound(String),
+    /// An operation of a batch failed and the batch was rolled back.
+    Batch(usize, Box<ApiError>),
+}
+
+impl ApiError {
+    fn kind(&self) -> (StatusCode, &'static str) {
+        match self {
+            ApiError::Batch(_, e) => e.kind(),
+            ApiError::Database(DieselError::NotFound) | ApiError::NotFound(_) => {
+                (StatusCode::NOT_FOUND, "not_found")
+            }
+            ApiError::Database(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                _,
+            )) => (StatusCode::CONFLICT, "conflict"),
+            ApiError::Database(DieselError::DatabaseError(
+                DatabaseErrorKind::NotNullViolation
+                | DatabaseErrorKind::CheckViolation
+                | DatabaseErrorKind::ForeignKeyViolation,
+                _,
+            ))
+            | ApiError::Validation(_) => (StatusCode::BAD_REQUEST, "validation_error"),
+            ApiError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, "database_error"),
+            ApiError::Pool(_) | ApiError::Blocking(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "database_unavailable")
+            }
+            ApiError::Auth(_) => (StatusCode::FORBIDDEN, "auth_failed"),
+            ApiError::SessionExpired(_) => (StatusCode::UNAUTHORIZED, "session_expired"),
+            ApiError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+            ApiError::Forbidden(_) => (StatusCode::FORBIDDEN, "forbidden"),
+            ApiError::QuotaExceeded(_) => (StatusCode::FORBIDDEN, "quota_exceeded"),
+        }
+    }
+
+    /// Machine readable error code, stable across releases.
+    pub fn code(&self) -> &'static str {
+        self.kind().1
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Database(e) => write!(f, "{}", e),
+            ApiError::Pool(e) => write!(f, "couldn't get db connection from pool: {}", e),
+            ApiError::Blocking(e) => write!(f, "couldn't run db query: {}", e),
+            ApiError::RateLimited(retry_after) => write!(
+                f,
+                "too many requests, retry in {}s",
+                retry_after_secs(*retry_after)
+            ),
+            ApiError::Auth(msg)
+            | ApiError::SessionExpired(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::QuotaExceeded(msg)
+            | ApiError::Validation(msg)
+            | ApiError::NotFound(msg) => write!(f, "{}", msg),
+            ApiError::Batch(index, e) => write!(
+                f,
+                "operation {} failed, nothing in the batch was applied: {}",
+                index, e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.kind().0
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        // don't leak database internals to the caller, log them instead
+        let message = if status.is_server_error() {
+            error!("{}", self);
+            status
+                .canonical_reason()
+                .unwrap_or("Internal Server Error")
+                .to_string()
+        } else {
+            self.to_string()
+        };
+        let mut res = HttpResponse::build(status);
+        if let ApiError::RateLimited(retry_after) = self {
+            res.insert_header((RETRY_AFTER, retry_after_secs(*retry_after).to_string()));
+        }
+        let mut error = json!({"code": self.code(), "message": message});
+        if let ApiError::Batch(index, _) = self {
+            error["index"] = json!(index);
+        }
+        res.json(json!({ "error": error }))
+    }
+}
+
+/// Whole seconds for Retry-After, rounded up so a client never retries too early.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)
+}
+
+impl From<DieselError> for ApiError {
+    fn from(e: DieselError) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl From<PoolError> for ApiError {
+    fn from(e: PoolError) -> Self {
+        ApiError::Pool(e)
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(e: BlockingError) -> Self {
+        ApiError::Blocking(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use diesel::result::DatabaseErrorInformation;
+    use serde_json::Value;
+
+    struct TestDbErrorInfo;
+
+    impl DatabaseErrorInformation for TestDbErrorInfo {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+        fn details(&self) -> Option<&str> {
+            None
+        }
+        fn hint(&self) -> Option<&str> {
+            None
+        }
+        fn table_name(&self) -> Option<&str> {
+            Some("contracts")
+        }
+        fn column_name(&self) -> Option<&str> {
+            None
+        }
+        fn constraint_name(&self) -> Option<&str> {
+            None
+        }
+        fn statement_position(&self) -> Option<i32> {
+            None
+        }
+    }
+
+    async fn body_json(err: ApiError) -> Value {
+        let res = err.error_response();
+        let body = to_bytes(res.into_body()).await.expect("Failed to get body");
+        serde_json::from_slice(&body).expect("Failed to parse json")
+    }
+
+    #[test]
+    fn test_status_codes() {
+        let cases = vec![
+            (
+                ApiError::Database(DieselError::NotFound),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                ApiError::Database(DieselError::DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    Box::new(TestDbErrorInfo),
+                )),
+                StatusCode::CONFLICT,
+            ),
+            (
+                ApiError::Database(DieselError::DatabaseError(
+                    DatabaseErrorKind::NotNullViolation,
+                    Box::new(TestDbErrorInfo),
+                )),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ApiError::Database(DieselError::BrokenTransactionManager),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (ApiError::Auth("bad sig".to_string()), StatusCode::FORBIDDEN),
+            (
+                ApiError::SessionExpired("expired".to_string()),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                ApiError::RateLimited(Duration::from_secs(1)),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                ApiError::Forbidden("unregistered".to_string()),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                ApiError::QuotaExceeded("full".to_string()),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                ApiError::Validation("bad query".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ApiError::NotFound("gone".to_string()),
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+        for (err, status) in cases {
+            assert_eq!(err.status_code(), status, "{:?}", err);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_client_errors_carry_message() {
+        assert_eq!(
+            body_json(ApiError::NotFound("No contract found".to_string())).await,
+            json!({"error": {"code": "not_found", "message": "No contract found"}})
+        );
+        assert_eq!(
+            body_json(ApiError::Database(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new(TestDbErrorInfo),
+            )))
+            .await,
+            json!({"error": {"code": "conflict", "message": "duplicate key value violates unique constraint"}})
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_batch_errors_carry_index() {
+        let err = ApiError::Batch(2, Box::new(ApiError::Database(DieselError::NotFound)));
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            body_json(err).await,
+            json!({"error": {
+                "code": "not_found",
+                "message": "operation 2 failed, nothing in the batch was applied: Record not found",
+                "index": 2,
+            }})
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_server_errors_hide_details() {
+        assert_eq!(
+            body_json(ApiError::Database(DieselError::BrokenTransactionManager)).await,
+            json!({"error": {"code": "database_error", "message": "Internal Server Error"}})
+        );
+    }
+    #[actix_web::test]
+    async fn test_rate_limited_has_retry_after() {
+        let res = ApiError::RateLimited(Duration::from_millis(1500)).error_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            res.headers().get(RETRY_AFTER).and_then(|h| h.to_str().ok()),
+            Some("2")
+        );
+    }
+}
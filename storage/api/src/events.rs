@@ -1,67 +1,152 @@
-use crate::DbPool;
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+use crate::openapi::{EffectedNum, ErrorBody};
 use actix_web::web;
-use actix_web::web::{Data, Json, Path};
-use actix_web::{delete, get, post, put, HttpResponse, Responder};
-use dlc_storage_common::models::{DeleteEvent, EventRequestParams, NewEvent, UpdateEvent};
-use log::warn;
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put, HttpResponse};
+use dlc_storage_common::models::{
+    BulkDeleteParams, DeleteEvent, Event, EventRequestParams, NewEvent, UpdateEvent,
+};
 use serde_json::json;
 
+#[utoipa::path(
+    tag = "events",
+    params(EventRequestParams),
+    responses(
+        (status = 200, description = "The events matching the filters", body = Vec<Event>),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[get("/events")]
 pub async fn get_events(
-    pool: Data<DbPool>,
+    pools: Data<Pools>,
     event_params: web::Query<EventRequestParams>,
-) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    let events = dlc_storage_reader::get_events(&mut conn, event_params.into_inner())
-        .expect("couldn't get events from db");
-    HttpResponse::Ok().json(events)
+) -> Result<HttpResponse, ApiError> {
+    let event_params = event_params.into_inner();
+    let key = event_params.key.clone();
+    let events = db::read(pools, "get_events", &key, move |conn| {
+        dlc_storage_reader::get_events(conn, event_params)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(events))
 }
 
+#[utoipa::path(
+    tag = "events",
+    responses(
+        (status = 200, description = "The created event", body = Event),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[post("/events")]
-pub async fn create_event(pool: Data<DbPool>, event: Json<NewEvent>) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    match dlc_storage_writer::create_event(&mut conn, event.into_inner()) {
-        Ok(event) => HttpResponse::Ok().json(event),
-        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
-    }
+pub async fn create_event(
+    pools: Data<Pools>,
+    event: Json<NewEvent>,
+) -> Result<HttpResponse, ApiError> {
+    let event = event.into_inner();
+    let event = db::write(pools, "create_event", event.key.clone(), move |conn| {
+        dlc_storage_writer::create_event(conn, event)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(event))
 }
 
+#[utoipa::path(
+    tag = "events",
+    responses(
+        (status = 200, description = "One event updated", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[put("/events")]
-pub async fn update_event(pool: Data<DbPool>, event: Json<UpdateEvent>) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    let num_updated = match dlc_storage_writer::update_event(&mut conn, event.into_inner()) {
-        Ok(num_updated) => num_updated,
-        Err(e) => {
-            warn!("Error updating event: {:?}", e);
-            return HttpResponse::BadRequest().body(e.to_string());
-        }
-    };
+pub async fn update_event(
+    pools: Data<Pools>,
+    event: Json<UpdateEvent>,
+) -> Result<HttpResponse, ApiError> {
+    let event = event.into_inner();
+    let num_updated = db::write(pools, "update_event", event.key.clone(), move |conn| {
+        dlc_storage_writer::update_event(conn, event)
+    })
+    .await?;
     match num_updated {
-        0 => HttpResponse::NotFound().body("No event found"),
-        _ => HttpResponse::Ok().json(json!({ "effected_num": num_updated })),
+        0 => Err(ApiError::NotFound("No event found".to_string())),
+        _ => Ok(HttpResponse::Ok().json(json!({ "effected_num": num_updated }))),
     }
 }
 
+#[utoipa::path(
+    tag = "events",
+    responses(
+        (status = 200, description = "The event is restored", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[post("/event/restore")]
+pub async fn restore_event(
+    pools: Data<Pools>,
+    event: Json<DeleteEvent>,
+) -> Result<HttpResponse, ApiError> {
+    let event = event.into_inner();
+    let num_restored = db::write(pools, "restore_event", event.key.clone(), move |conn| {
+        dlc_storage_writer::restore_event(conn, event)
+    })
+    .await?;
+    match num_restored {
+        0 => Err(ApiError::NotFound("No deleted event found".to_string())),
+        _ => Ok(HttpResponse::Ok().json(json!({ "effected_num": num_restored }))),
+    }
+}
+
+#[utoipa::path(
+    tag = "events",
+    responses(
+        (status = 200, description = "The event is soft deleted", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[delete("/event")]
-pub async fn delete_event(pool: Data<DbPool>, event: Json<DeleteEvent>) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    let num_deleted = match dlc_storage_writer::delete_event(&mut conn, event.into_inner()) {
-        Ok(num_deleted) => num_deleted,
-        Err(e) => {
-            warn!("Error deleting event: {:?}", e);
-            return HttpResponse::BadRequest().body(e.to_string());
-        }
-    };
+pub async fn delete_event(
+    pools: Data<Pools>,
+    event: Json<DeleteEvent>,
+) -> Result<HttpResponse, ApiError> {
+    let event = event.into_inner();
+    let num_deleted = db::write(pools, "delete_event", event.key.clone(), move |conn| {
+        dlc_storage_writer::delete_event(conn, event)
+    })
+    .await?;
     match num_deleted {
-        0 => HttpResponse::NotFound().body("No event found"),
-        _ => HttpResponse::Ok().json(json!({ "effected_num": num_deleted })),
+        0 => Err(ApiError::NotFound("No event found".to_string())),
+        _ => Ok(HttpResponse::Ok().json(json!({ "effected_num": num_deleted }))),
     }
 }
 
+/// Soft deletes every event of a key, only with `confirm=true`.
+#[utoipa::path(
+    tag = "events",
+    params(
+        BulkDeleteParams,
+        ("ckey" = String, Path, description = "The public key whose events to delete"),
+    ),
+    responses(
+        (status = 200, description = "The events of the key are soft deleted", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
 #[delete("/events/{ckey}")]
-pub async fn delete_events(pool: Data<DbPool>, ckey: Path<String>) -> impl Responder {
-    let mut conn = pool.get().expect("couldn't get db connection from pool");
-    let num_deleted = dlc_storage_writer::delete_events(&mut conn, &ckey)
-        .expect("couldn't delete events from db");
-    HttpResponse::Ok().json(json!({ "effected_num": num_deleted }))
+pub async fn delete_events(
+    pools: Data<Pools>,
+    ckey: Path<String>,
+    params: Query<BulkDeleteParams>,
+) -> Result<HttpResponse, ApiError> {
+    if params.confirm != Some(true) {
+        return Err(ApiError::Validation(
+            "deleting all events of a key needs confirm=true".to_string(),
+        ));
+    }
+    let ckey = ckey.into_inner();
+    let num_deleted = db::write(pools, "delete_all_events", ckey.clone(), move |conn| {
+        dlc_storage_writer::delete_events(conn, &ckey)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "effected_num": num_deleted })))
 }
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use actix_web::web::{self, Data};
+use actix_web::{get, HttpResponse, Responder};
+use diesel::connection::SimpleConnection;
+use serde::Serialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::db::{DbPool, Pools};
+use crate::openapi::Health;
+
+/// How long readiness waits for a connection, well below the usual probe timeouts.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The status of one thing the API needs to serve requests.
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct Component {
+    pub component: &'static str,
+    pub status: &'static str,
+    pub message: String,
+}
+
+impl Component {
+    fn check(component: &'static str, result: Result<(), String>) -> Self {
+        let (status, message) = match result {
+            Ok(()) => ("healthy", String::new()),
+            Err(message) => ("unhealthy", message),
+        };
+        Self {
+            component,
+            status,
+            message,
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.status == "healthy"
+    }
+}
+
+/// Liveness, kept at its old path.
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "The process is up")
+    )
+)]
+#[get("/health")]
+pub async fn get_health() -> impl Responder {
+    live().await
+}
+
+/// Liveness: the process is up and answering. It doesn't look at the database, so a
+/// database outage doesn't get the API restarted.
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "The process is up")
+    )
+)]
+#[get("/health/live")]
+pub async fn get_health_live() -> impl Responder {
+    live().await
+}
+
+async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(json!({"data": [{"status": "healthy", "message": ""}]}))
+}
+
+/// Readiness: the database answers, its migrations are applied and the connection pools
+/// have room. 503 if any component is unhealthy, so traffic goes to other replicas.
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Every component is healthy", body = Health),
+        (status = 503, description = "Some component is unhealthy", body = Health)
+    )
+)]
+#[get("/health/ready")]
+pub async fn get_health_ready(pools: Data<Pools>) -> HttpResponse {
+    let components = match web::block(move || check_components(&pools)).await {
+        Ok(components) => components,
+        Err(e) => vec![Component::check("database", Err(e.to_string()))],
+    };
+    let mut res = if components.iter().all(Component::is_healthy) {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    res.json(json!({ "data": components }))
+}
+
+fn check_components(pools: &Pools) -> Vec<Component> {
+    let mut components = vec![
+        Component::check("db_pool", check_pool(pools.writer())),
+        Component::check("database", check_database(pools.writer())),
+        Component::check("migrations", check_migrations(pools.writer())),
+    ];
+    if let Some(replica) = pools.replica() {
+        components.push(Component::check("read_db_pool", check_pool(replica)));
+        components.push(Component::check("read_database", check_database(replica)));
+    }
+    components
+}
+
+/// A pool is saturated when every connection it may open is in use.
+fn check_pool(pool: &DbPool) -> Result<(), String> {
+    let state = pool.state();
+    if state.idle_connections == 0 && state.connections >= pool.max_size() {
+        return Err(format!("all {} connections are in use", state.connections));
+    }
+    Ok(())
+}
+
+fn check_database(pool: &DbPool) -> Result<(), String> {
+    let mut conn = pool.get_timeout(READY_TIMEOUT).map_err(|e| e.to_string())?;
+    conn.batch_execute("SELECT 1").map_err(|e| e.to_string())
+}
+
+fn check_migrations(pool: &DbPool) -> Result<(), String> {
+    let mut conn = pool.get_timeout(READY_TIMEOUT).map_err(|e| e.to_string())?;
+    match dlc_storage_writer::pending_migrations(&mut conn).map_err(|e| e.to_string())? {
+        0 => Ok(()),
+        pending => Err(format!("{} migrations are pending", pending)),
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web::{self, Data},
+    Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use log::warn;
+use prometheus::{IntCounterVec, IntGaugeVec, Opts, Registry};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+use crate::verify_sigs::{bytes_to_payload, SignedBy};
+
+/// Past this many tracked clients, buckets that have refilled are dropped.
+const MAX_BUCKETS: usize = 100_000;
+
+/// A token bucket per client: `per_min` requests in a burst, refilling at `per_min`
+/// a minute. A limit of 0 lets everything through.
+pub struct RateLimiter {
+    per_min: u32,
+    buckets: HashMap<String, (f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(per_min: u32) -> Self {
+        Self {
+            per_min,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket, or says how long until there is one.
+    pub fn take(&mut self, client: &str) -> Result<(), Duration> {
+        if self.per_min == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(self.per_min);
+        let per_sec = capacity / 60.0;
+        if self.buckets.len() >= MAX_BUCKETS {
+            self.buckets.retain(|_, (tokens, last)| {
+                *tokens + last.elapsed().as_secs_f64() * per_sec < capacity
+            });
+        }
+        let now = Instant::now();
+        let (tokens, last) = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert((capacity, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * per_sec).min(capacity);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / per_sec))
+        }
+    }
+}
+
+/// The rate limits and storage quotas of the API, and their metrics.
+pub struct Limits {
+    nonces_per_ip: Mutex<RateLimiter>,
+    mutations_per_ip: Mutex<RateLimiter>,
+    mutations_per_key: Mutex<RateLimiter>,
+    max_rows_per_key: u64,
+    max_content_bytes_per_key: u64,
+    trust_forwarded_for: bool,
+    rejections: IntCounterVec,
+    configured: IntGaugeVec,
+}
+
+impl Limits {
+    pub fn from_config(config: &Config) -> Self {
+        let rejections = IntCounterVec::new(
+            Opts::new(
+                "limit_rejections_total",
+                "Requests rejected by a rate limit or storage quota",
+            ),
+            &["limit"],
+        )
+        .expect("should create limit_rejections_total counter");
+        let configured = IntGaugeVec::new(
+            Opts::new(
+                "limit_configured",
+                "Configured rate limits (per minute) and quotas, 0 for no limit",
+            ),
+            &["limit"],
+        )
+        .expect("should create limit_configured gauge");
+        for (limit, value) in [
+            ("nonce_per_ip", u64::from(config.nonce_rate_limit_per_ip)),
+            (
+                "mutation_per_ip",
+                u64::from(config.mutation_rate_limit_per_ip),
+            ),
+            (
+                "mutation_per_key",
+                u64::from(config.mutation_rate_limit_per_key),
+            ),
+            ("rows_per_key", config.max_rows_per_key),
+            ("content_bytes_per_key", config.max_content_bytes_per_key),
+        ] {
+            configured
+                .with_label_values(&[limit])
+                .set(i64::try_from(value).unwrap_or(i64::MAX));
+        }
+        Self {
+            nonces_per_ip: Mutex::new(RateLimiter::new(config.nonce_rate_limit_per_ip)),
+            mutations_per_ip: Mutex::new(RateLimiter::new(config.mutation_rate_limit_per_ip)),
+            mutations_per_key: Mutex::new(RateLimiter::new(config.mutation_rate_limit_per_key)),
+            max_rows_per_key: config.max_rows_per_key,
+            max_content_bytes_per_key: config.max_content_bytes_per_key,
+            trust_forwarded_for: config.trust_forwarded_for,
+            rejections,
+            configured,
+        }
+    }
+
+    pub fn register(&self, registry: &Registry) {
+        registry
+            .register(Box::new(self.rejections.clone()))
+            .expect("should register limit_rejections_total counter");
+        registry
+            .register(Box::new(self.configured.clone()))
+            .expect("should register limit_configured gauge");
+    }
+
+    fn take(&self, limiter: &Mutex<RateLimiter>, name: &str, client: &str) -> Result<(), ApiError> {
+        limiter
+            .lock()
+            .expect("unable to lock rate limiter")
+            .take(client)
+            .map_err(|retry_after| {
+                self.rejections.with_label_values(&[name]).inc();
+                ApiError::RateLimited(retry_after)
+            })
+    }
+
+    fn has_quotas(&self) -> bool {
+        self.max_rows_per_key > 0 || self.max_content_bytes_per_key > 0
+    }
+
+    /// Checks that `key` can store what `writes` add: a row for each one that creates or
+    /// restores a row that isn't stored yet, and the growth of the content of each.
+    async fn check_quota(
+        &self,
+        pools: Data<Pools>,
+        key: String,
+        writes: Vec<Write>,
+    ) -> Result<(), ApiError> {
+        let ids = |event: bool| -> Vec<String> {
+            writes
+                .iter()
+                .filter(|w| w.event == event)
+                .map(|w| w.id.clone())
+                .collect()
+        };
+        let (uuids, event_ids) = (ids(false), ids(true));
+        let (usage, lengths) = db::read(pools, "get_usage", &key.clone(), move |conn| {
+            let usage = dlc_storage_reader::get_usage(conn, &key)?;
+            let lengths = dlc_storage_reader::get_content_lengths(conn, &key, &uuids, &event_ids)?;
+            Ok((usage, lengths))
+        })
+        .await?;
+        let (mut rows, mut content_bytes) = (0, 0);
+        for write in &writes {
+            let stored = if write.event {
+                &lengths.events
+            } else {
+                &lengths.contracts
+            };
+            let stored = stored.get(&write.id);
+            let live_len = stored.filter(|s| !s.deleted).map(|s| s.length);
+            if write.creates && live_len.is_none() {
+                rows += 1;
+            }
+            content_bytes += match write.content_len {
+                Some(content_len) => content_len - live_len.unwrap_or(0),
+                None if write.creates && live_len.is_none() => stored.map_or(0, |s| s.length),
+                None => 0,
+            };
+        }
+        // writes that don't grow the key are let through even over the quota
+        let exceeds = |used: i64, added: i64, max: u64| {
+            max > 0 && added > 0 && u64::try_from(used + added).unwrap_or_default() > max
+        };
+        if exceeds(usage.rows, rows, self.max_rows_per_key) {
+            self.rejections.with_label_values(&["rows_per_key"]).inc();
+            return Err(ApiError::QuotaExceeded(format!(
+                "key already stores {} of {} contracts and events",
+                usage.rows, self.max_rows_per_key
+            )));
+        }
+        if exceeds(
+            usage.content_bytes,
+            content_bytes,
+            self.max_content_bytes_per_key,
+        ) {
+            self.rejections
+                .with_label_values(&["content_bytes_per_key"])
+                .inc();
+            return Err(ApiError::QuotaExceeded(format!(
+                "key already stores {} of {} bytes of content",
+                usage.content_bytes, self.max_content_bytes_per_key
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Enforces [`Limits`] from app data. It wraps the services inside the [`Verifier`],
+/// so keys are only charged for requests they signed, and signed requests can only
+/// write the data of their signer.
+///
+/// [`Verifier`]: crate::verify_sigs::Verifier
+pub struct Limiter;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for Limiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LimiterMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LimiterMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct LimiterMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for LimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        Box::pin(async move {
+            let Some(limits) = req.app_data::<Data<Limits>>().cloned() else {
+                return Ok(svc.call(req).await?.map_into_left_body());
+            };
+            if let Err(e) = check(&limits, &mut req).await {
+                return Ok(req.error_response(e).map_into_right_body());
+            }
+            Ok(svc.call(req).await?.map_into_left_body())
+        })
+    }
+}
+
+async fn check(limits: &Limits, req: &mut ServiceRequest) -> Result<(), ApiError> {
+    let ip = client_ip(req, limits.trust_forwarded_for);
+    if req.path() == "/request_nonce" {
+        return limits.take(&limits.nonces_per_ip, "nonce_per_ip", &ip);
+    }
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+    limits.take(&limits.mutations_per_ip, "mutation_per_ip", &ip)?;
+
+    let body = req
+        .extract::<web::Bytes>()
+        .await
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    req.set_payload(bytes_to_payload(body.clone()));
+    let body = serde_json::from_slice::<Value>(&body).unwrap_or_default();
+    let keys = mutation_keys(req.path(), &body);
+    // unsigned (v1) requests are charged to the key they write
+    let key = match req.extensions().get::<SignedBy>() {
+        Some(SignedBy(signer)) if keys.iter().any(|key| key != signer) => {
+            return Err(ApiError::Forbidden(
+                "signed requests can only write the signer's data".to_string(),
+            ))
+        }
+        Some(SignedBy(signer)) => signer.clone(),
+        None => match keys.into_iter().next() {
+            Some(key) => key,
+            None => return Ok(()),
+        },
+    };
+    limits.take(&limits.mutations_per_key, "mutation_per_key", &key)?;
+
+    if !limits.has_quotas() {
+        return Ok(());
+    }
+    let writes = match (req.method(), req.path()) {
+        (&Method::POST, "/contracts") => vec![Write::new(&body, false, true)],
+        (&Method::POST, "/events") => vec![Write::new(&body, true, true)],
+        (&Method::PUT, "/contracts") => vec![Write::new(&body, false, false)],
+        (&Method::PUT, "/events") => vec![Write::new(&body, true, false)],
+        (&Method::POST, "/contract/restore") => vec![Write::new(&body, false, true)],
+        (&Method::POST, "/event/restore") => vec![Write::new(&body, true, true)],
+        (&Method::POST, "/batch") => {
+            let operations = body["operations"].as_array().into_iter().flatten();
+            operations
+                .filter_map(|op| match op["op"].as_str()? {
+                    "create_contract" => Some(Write::new(op, false, true)),
+                    "create_event" => Some(Write::new(op, true, true)),
+                    "update_contract" => Some(Write::new(op, false, false)),
+                    "update_event" => Some(Write::new(op, true, false)),
+                    _ => None,
+                })
+                .collect()
+        }
+        (&Method::POST, "/import") => {
+            let archive = &body["archive"];
+            let contracts = archive["contracts"].as_array().into_iter().flatten();
+            let events = archive["events"].as_array().into_iter().flatten();
+            contracts
+                .map(|row| Write::new(row, false, true))
+                .chain(events.map(|row| Write::new(row, true, true)))
+                .collect()
+        }
+        _ => return Ok(()),
+    };
+    let Some(pools) = req.app_data::<Data<Pools>>().cloned() else {
+        return Ok(());
+    };
+    limits.check_quota(pools, key, writes).await
+}
+
+/// A contract or event a request writes, with what it needs to be charged.
+struct Write {
+    event: bool,
+    /// The uuid or event id.
+    id: String,
+    /// Whether the write creates the row if it isn't stored yet, or restores it.
+    creates: bool,
+    /// The length of the content it stores, none if it keeps the stored content (which a
+    /// restore brings back).
+    content_len: Option<i64>,
+}
+
+impl Write {
+    fn new(body: &Value, event: bool, creates: bool) -> Self {
+        let id = if event { "event_id" } else { "uuid" };
+        Self {
+            event,
+            id: body[id].as_str().unwrap_or_default().to_string(),
+            creates,
+            content_len: body["content"].as_str().map(|c| c.len() as i64),
+        }
+    }
+}
+
+/// The keys a mutation names: the `key` of its body, the key of an imported archive,
+/// and the key in the path of a bulk delete.
+pub(crate) fn mutation_keys(path: &str, body: &Value) -> Vec<String> {
+    let path_key = path
+        .strip_prefix("/contracts/")
+        .or_else(|| path.strip_prefix("/events/"));
+    [
+        body["key"].as_str(),
+        body["archive"]["key"].as_str(),
+        path_key,
+    ]
+    .into_iter()
+    .flatten()
+    .map(str::to_string)
+    .collect()
+}
+
+/// The IP rate limits apply to. Forwarded headers are easily spoofed, so they are only
+/// used when the API runs behind a proxy that sets them.
+fn client_ip(req: &ServiceRequest, trust_forwarded_for: bool) -> String {
+    let info = req.connection_info();
+    let ip = if trust_forwarded_for {
+        info.realip_remote_addr()
+    } else {
+        info.peer_addr()
+    };
+    match ip {
+        Some(ip) => ip.to_string(),
+        None => {
+            warn!("could not tell the client IP of a request");
+            "unknown".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2);
+        assert!(limiter.take("a").is_ok());
+        assert!(limiter.take("a").is_ok());
+        let retry_after = limiter.take("a").expect_err("bucket should be empty");
+        assert!(retry_after > Duration::from_secs(25) && retry_after <= Duration::from_secs(30));
+        // other clients have their own bucket
+        assert!(limiter.take("b").is_ok());
+
+        let mut unlimited = RateLimiter::new(0);
+        for _ in 0..1000 {
+            assert!(unlimited.take("a").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_mutation_keys() {
+        let body = serde_json::json!({"key": "k1", "uuid": "1"});
+        assert_eq!(mutation_keys("/contracts", &body), ["k1"]);
+        let body = serde_json::json!({"archive": {"key": "k2"}});
+        assert_eq!(mutation_keys("/import", &body), ["k2"]);
+        assert_eq!(mutation_keys("/events/k3", &Value::Null), ["k3"]);
+        let body = serde_json::json!({"key": "k1"});
+        assert_eq!(mutation_keys("/contracts/k3", &body), ["k1", "k3"]);
+        assert!(mutation_keys("/login", &serde_json::json!({})).is_empty());
+    }
+}
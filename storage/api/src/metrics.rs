@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use actix_web::web::Data;
+use log::error;
+use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+
+/// What the API stores and how its authentication fares, next to the request metrics
+/// of actix-web-prometheus and the query durations of [`Pools`].
+pub struct Metrics {
+    contracts: IntGaugeVec,
+    events: IntGaugeVec,
+    auth_failures: IntCounterVec,
+    nonce_pool_size: IntGauge,
+    nonce_evictions: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            contracts: IntGaugeVec::new(
+                Opts::new(
+                    "contracts",
+                    "Stored contracts by state, without deleted ones",
+                ),
+                &["state"],
+            )
+            .expect("should create contracts gauge"),
+            events: IntGaugeVec::new(
+                Opts::new(
+                    "events",
+                    "Stored events by whether they are attested, without deleted ones",
+                ),
+                &["attested"],
+            )
+            .expect("should create events gauge"),
+            auth_failures: IntCounterVec::new(
+                Opts::new(
+                    "auth_failures_total",
+                    "Requests rejected for failing authentication",
+                ),
+                &["reason"],
+            )
+            .expect("should create auth_failures_total counter"),
+            nonce_pool_size: IntGauge::new("nonce_pool_size", "Issued nonces not used up yet")
+                .expect("should create nonce_pool_size gauge"),
+            nonce_evictions: IntCounter::new(
+                "nonce_evictions_total",
+                "Nonces dropped unused, because they expired or the pool was full",
+            )
+            .expect("should create nonce_evictions_total counter"),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn register(&self, registry: &Registry) {
+        registry
+            .register(Box::new(self.contracts.clone()))
+            .expect("should register contracts gauge");
+        registry
+            .register(Box::new(self.events.clone()))
+            .expect("should register events gauge");
+        registry
+            .register(Box::new(self.auth_failures.clone()))
+            .expect("should register auth_failures_total counter");
+        registry
+            .register(Box::new(self.nonce_pool_size.clone()))
+            .expect("should register nonce_pool_size gauge");
+        registry
+            .register(Box::new(self.nonce_evictions.clone()))
+            .expect("should register nonce_evictions_total counter");
+    }
+
+    pub fn auth_failed(&self, reason: &str) {
+        self.auth_failures.with_label_values(&[reason]).inc();
+    }
+
+    /// Records the size of the nonce pool, and how many nonces were just evicted from it.
+    pub fn nonces(&self, pool_size: usize, evicted: usize) {
+        self.nonce_pool_size
+            .set(i64::try_from(pool_size).unwrap_or(i64::MAX));
+        self.nonce_evictions.inc_by(evicted as u64);
+    }
+
+    /// Counts what is stored. States no contract is in anymore are dropped.
+    pub async fn refresh(&self, pools: Data<Pools>) -> Result<(), ApiError> {
+        let stats = db::read(pools, "get_stats", "", dlc_storage_reader::get_stats).await?;
+        self.contracts.reset();
+        for (state, count) in stats.contracts_by_state {
+            self.contracts.with_label_values(&[&state]).set(count);
+        }
+        self.events
+            .with_label_values(&["true"])
+            .set(stats.attested_events);
+        self.events
+            .with_label_values(&["false"])
+            .set(stats.unattested_events);
+        Ok(())
+    }
+}
+
+/// Refreshes the counts of `metrics` every `interval`, unless it is zero.
+pub fn spawn(metrics: Data<Metrics>, pools: Data<Pools>, interval: Duration) {
+    if interval.is_zero() {
+        return;
+    }
+    actix_web::rt::spawn(async move {
+        let mut ticks = actix_web::rt::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            if let Err(e) = metrics.refresh(pools.clone()).await {
+                error!("Counting stored contracts and events failed: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,164 @@
+use actix_web::{get, HttpResponse};
+use dlc_storage_common::models::{
+    Archive, ArchivedContract, ArchivedEvent, BatchOperation, BatchRequest, BatchResponse,
+    BatchResult, Change, Contract, DeleteContract, DeleteEvent, Event, ImportSummary, NewContract,
+    NewEvent, RegisterKey, RegisteredKey, Role, SignedArchive, UnregisterKey, UpdateContract,
+    UpdateEvent,
+};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::health::Component;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "dlc-storage API",
+        description = "Stores the contracts and events of DLC wallets and attestors, by the public key \
+that owns them. Writes are sent as `{\"message\": <body>, \"public_key\": ..., \"signature\": ...}`, \
+GETs carry a `signature` query parameter, and both are signed over a nonce from `/request_nonce` in the \
+`authorization` header, or authorized by a session token from `/login` instead. The bodies documented \
+here are the signed messages.",
+    ),
+    paths(
+        crate::request_nonce,
+        crate::sessions::login,
+        crate::health::get_health,
+        crate::health::get_health_live,
+        crate::health::get_health_ready,
+        crate::contracts::get_contracts,
+        crate::contracts::create_contract,
+        crate::contracts::update_contract,
+        crate::contracts::delete_contract,
+        crate::contracts::delete_contracts,
+        crate::contracts::restore_contract,
+        crate::events::get_events,
+        crate::events::create_event,
+        crate::events::update_event,
+        crate::events::delete_event,
+        crate::events::delete_events,
+        crate::events::restore_event,
+        crate::changes::get_changes,
+        crate::archive::export_key,
+        crate::archive::import_archive,
+        crate::batch::apply_batch,
+        crate::registration::get_registered_keys,
+        crate::registration::register_key,
+        crate::registration::unregister_key,
+        get_openapi,
+    ),
+    components(schemas(
+        Contract,
+        NewContract,
+        UpdateContract,
+        DeleteContract,
+        Event,
+        NewEvent,
+        UpdateEvent,
+        DeleteEvent,
+        Change,
+        Archive,
+        ArchivedContract,
+        ArchivedEvent,
+        SignedArchive,
+        ImportSummary,
+        BatchRequest,
+        BatchOperation,
+        BatchResponse,
+        BatchResult,
+        RegisteredKey,
+        RegisterKey,
+        UnregisterKey,
+        Role,
+        EffectedNum,
+        Session,
+        Health,
+        Component,
+        ErrorBody,
+        ErrorDetail,
+    ))
+)]
+pub struct ApiDoc;
+
+/// How many rows a write touched.
+#[derive(Serialize, ToSchema)]
+pub struct EffectedNum {
+    pub effected_num: usize,
+}
+
+/// A session token from `/login`, good for `expires_in_secs`.
+#[derive(Serialize, ToSchema)]
+pub struct Session {
+    pub token: String,
+    pub key: String,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Health {
+    pub data: Vec<Component>,
+}
+
+/// The body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail {
+    /// Machine readable, e.g. `not_found` or `rate_limited`.
+    pub code: String,
+    pub message: String,
+}
+
+/// This document.
+#[utoipa::path(responses((status = 200, description = "The OpenAPI document of the API")))]
+#[get("/openapi.json")]
+pub async fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_and_read_body_json, init_service, TestRequest};
+    use actix_web::App;
+    use serde_json::Value;
+
+    /// Where the committed copy of the document lives, relative to the api crate. Clients
+    /// check their types against it.
+    const OPENAPI_JSON: &str = "../openapi.json";
+
+    #[actix_web::test]
+    async fn test_get_openapi() {
+        let app = init_service(App::new().service(get_openapi)).await;
+        let req = TestRequest::get().uri("/openapi.json").to_request();
+        let doc: Value = call_and_read_body_json(&app, req).await;
+        assert!(doc["paths"]["/contracts"]["get"].is_object(), "{}", doc);
+        assert!(
+            doc["components"]["schemas"]["Contract"].is_object(),
+            "{}",
+            doc
+        );
+    }
+
+    /// Fails when the handlers or models change without `openapi.json` being
+    /// regenerated, with `UPDATE_OPENAPI=1 cargo test openapi` (or `just openapi`).
+    #[test]
+    fn test_openapi_json_is_up_to_date() {
+        let generated = ApiDoc::openapi()
+            .to_pretty_json()
+            .expect("should serialize the OpenAPI document")
+            + "\n";
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(OPENAPI_JSON, &generated).expect("should write openapi.json");
+            return;
+        }
+        let committed = std::fs::read_to_string(OPENAPI_JSON).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "openapi.json is out of date, regenerate it with `just openapi`"
+        );
+    }
+}
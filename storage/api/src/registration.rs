@@ -0,0 +1,404 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    delete,
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    get,
+    http::Method,
+    post,
+    web::{self, Data, Json},
+    Error, HttpMessage, HttpRequest, HttpResponse,
+};
+use dlc_storage_common::models::{
+    AdminRequestParams, RegisterKey, RegisteredKey, Role, UnregisterKey,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+use crate::limits::mutation_keys;
+use crate::metrics::Metrics;
+use crate::openapi::{EffectedNum, ErrorBody};
+use crate::verify_sigs::{bytes_to_payload, SignedBy};
+use crate::UnprotectedPaths;
+
+/// Whether only registered keys are served, and the key that registers them.
+pub struct Registration {
+    pub required: bool,
+    pub admin_key: Option<String>,
+}
+
+impl Registration {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            required: config.require_registration,
+            admin_key: config.admin_key.clone(),
+        }
+    }
+
+    /// Fails unless the request was signed by the admin key.
+    fn check_admin(&self, req: &HttpRequest) -> Result<(), ApiError> {
+        let Some(admin_key) = &self.admin_key else {
+            return Err(ApiError::Forbidden(
+                "no admin key is configured".to_string(),
+            ));
+        };
+        match req.extensions().get::<SignedBy>() {
+            Some(SignedBy(key)) if key == admin_key => Ok(()),
+            _ => Err(ApiError::Forbidden(
+                "only the admin key can manage registrations".to_string(),
+            )),
+        }
+    }
+}
+
+/// What a request writes, for the role permissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entity {
+    Contract,
+    Event,
+}
+
+impl Entity {
+    fn plural(&self) -> &'static str {
+        match self {
+            Entity::Contract => "contracts",
+            Entity::Event => "events",
+        }
+    }
+}
+
+/// Attestors only write events, wallets and router wallets only contracts.
+fn may_write(role: Role, entity: Entity) -> bool {
+    match role {
+        Role::Attestor => entity == Entity::Event,
+        Role::RouterWallet | Role::Wallet => entity == Entity::Contract,
+    }
+}
+
+/// End user wallets write their contracts one at a time, the batches, imports and
+/// bulk deletes are left to the attestors and router wallets the operator runs.
+fn may_write_in_bulk(role: Role) -> bool {
+    match role {
+        Role::Attestor | Role::RouterWallet => true,
+        Role::Wallet => false,
+    }
+}
+
+/// Whether a mutation of `path` writes many rows at once.
+fn is_bulk(method: &Method, path: &str) -> bool {
+    match path {
+        "/batch" | "/import" => true,
+        path => {
+            *method == Method::DELETE
+                && (path.starts_with("/contracts/") || path.starts_with("/events/"))
+        }
+    }
+}
+
+/// What a mutation of `path` with `body` writes: the entity in its path, the
+/// operations of a batch, or the contents of an imported archive.
+fn written_entities(path: &str, body: &Value) -> Vec<Entity> {
+    let entity = |name: &str| {
+        if name.contains("contract") {
+            Some(Entity::Contract)
+        } else if name.contains("event") {
+            Some(Entity::Event)
+        } else {
+            None
+        }
+    };
+    match path {
+        "/batch" => body["operations"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|op| op["op"].as_str().and_then(entity))
+            .collect(),
+        "/import" => {
+            let archive = &body["archive"];
+            let has = |rows: &str| {
+                archive[rows]
+                    .as_array()
+                    .is_some_and(|rows| !rows.is_empty())
+            };
+            [
+                has("contracts").then_some(Entity::Contract),
+                has("events").then_some(Entity::Event),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        }
+        path => entity(path).into_iter().collect(),
+    }
+}
+
+/// Enforces the [`Registration`] from app data: when registration is required, only
+/// registered keys get past it, and they only write their own data, of the entities
+/// their role may write. It wraps the services inside the [`Verifier`], which tells it
+/// who signed a request. The [`Limiter`] keeps signed writes to the signer's own data
+/// whether or not registration is required, this checks it again so it doesn't depend
+/// on the order the middlewares wrap in.
+///
+/// [`Verifier`]: crate::verify_sigs::Verifier
+/// [`Limiter`]: crate::limits::Limiter
+pub struct Allowlist;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for Allowlist
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AllowlistMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AllowlistMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AllowlistMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AllowlistMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        Box::pin(async move {
+            let registration = req.app_data::<Data<Registration>>().cloned();
+            match registration {
+                Some(registration) if registration.required => {
+                    if let Err(e) = check(&mut req).await {
+                        if let (ApiError::Auth(_) | ApiError::Forbidden(_), Some(metrics)) =
+                            (&e, req.app_data::<Data<Metrics>>())
+                        {
+                            metrics.auth_failed("not_allowed");
+                        }
+                        return Ok(req.error_response(e).map_into_right_body());
+                    }
+                }
+                _ => {}
+            }
+            Ok(svc.call(req).await?.map_into_left_body())
+        })
+    }
+}
+
+async fn check(req: &mut ServiceRequest) -> Result<(), ApiError> {
+    let unprotected = req
+        .app_data::<Data<UnprotectedPaths>>()
+        .is_some_and(|unprotected| unprotected.paths.iter().any(|p| p == req.path()));
+    // admin requests are checked by their handlers, sessions when they are used
+    if unprotected || req.path().starts_with("/admin/") || req.path() == "/login" {
+        return Ok(());
+    }
+    let Some(signer) = req.extensions().get::<SignedBy>().map(|s| s.0.clone()) else {
+        return Err(ApiError::Auth(
+            "registration requires signed requests".to_string(),
+        ));
+    };
+
+    let pools = req
+        .app_data::<Data<Pools>>()
+        .cloned()
+        .expect("unable to get pools from app data");
+    // a replica could still serve a key the admin just unregistered
+    let ckey = signer.clone();
+    let registered = db::read_primary(pools, "get_registered_key", move |conn| {
+        dlc_storage_reader::get_registered_key(conn, &ckey)
+    })
+    .await?;
+    let Some(registered) = registered else {
+        return Err(ApiError::Forbidden(format!("{} is not registered", signer)));
+    };
+    let role = registered
+        .role
+        .parse::<Role>()
+        .map_err(ApiError::Forbidden)?;
+
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+    let body = req
+        .extract::<web::Bytes>()
+        .await
+        .map_err(|e| ApiError::Validation(e.to_string()))?;
+    req.set_payload(bytes_to_payload(body.clone()));
+    let body = serde_json::from_slice::<Value>(&body).unwrap_or_default();
+    if mutation_keys(req.path(), &body)
+        .iter()
+        .any(|key| *key != signer)
+    {
+        return Err(ApiError::Forbidden(
+            "registered keys can only write their own data".to_string(),
+        ));
+    }
+    if is_bulk(req.method(), req.path()) && !may_write_in_bulk(role) {
+        return Err(ApiError::Forbidden(format!(
+            "{} keys can't write in bulk",
+            role.as_str()
+        )));
+    }
+    match written_entities(req.path(), &body)
+        .into_iter()
+        .find(|entity| !may_write(role, *entity))
+    {
+        Some(entity) => Err(ApiError::Forbidden(format!(
+            "{} keys can't write {}",
+            role.as_str(),
+            entity.plural()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Every registered key, for the admin key.
+#[utoipa::path(
+    tag = "admin",
+    params(AdminRequestParams),
+    responses(
+        (status = 200, description = "The registered keys", body = Vec<RegisteredKey>),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[get("/admin/keys")]
+pub async fn get_registered_keys(
+    req: HttpRequest,
+    pools: Data<Pools>,
+    registration: Data<Registration>,
+    _params: web::Query<AdminRequestParams>,
+) -> Result<HttpResponse, ApiError> {
+    registration.check_admin(&req)?;
+    let keys = db::read(
+        pools,
+        "get_registered_keys",
+        "",
+        dlc_storage_reader::get_registered_keys,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// Registers a key, or changes its role, for the admin key.
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "The registered key", body = RegisteredKey),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[post("/admin/keys")]
+pub async fn register_key(
+    req: HttpRequest,
+    pools: Data<Pools>,
+    registration: Data<Registration>,
+    params: Json<RegisterKey>,
+) -> Result<HttpResponse, ApiError> {
+    registration.check_admin(&req)?;
+    let RegisterKey {
+        public_key, role, ..
+    } = params.into_inner();
+    let registered = db::write(pools, "register_key", public_key.clone(), move |conn| {
+        dlc_storage_writer::register_key(conn, &public_key, role.as_str())
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(registered))
+}
+
+/// Removes a registered key, for the admin key. What the key stored is kept.
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "The key is no longer registered", body = EffectedNum),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[delete("/admin/keys")]
+pub async fn unregister_key(
+    req: HttpRequest,
+    pools: Data<Pools>,
+    registration: Data<Registration>,
+    params: Json<UnregisterKey>,
+) -> Result<HttpResponse, ApiError> {
+    registration.check_admin(&req)?;
+    let public_key = params.into_inner().public_key;
+    let num_deleted = db::write(pools, "unregister_key", public_key.clone(), move |conn| {
+        dlc_storage_writer::unregister_key(conn, &public_key)
+    })
+    .await?;
+    match num_deleted {
+        0 => Err(ApiError::NotFound("No registered key found".to_string())),
+        _ => Ok(HttpResponse::Ok().json(json!({ "effected_num": num_deleted }))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_written_entities() {
+        assert_eq!(
+            written_entities("/contracts", &Value::Null),
+            vec![Entity::Contract]
+        );
+        assert_eq!(
+            written_entities("/event/restore", &Value::Null),
+            vec![Entity::Event]
+        );
+        let batch = json!({"operations": [{"op": "create_event"}, {"op": "delete_contract"}]});
+        assert_eq!(
+            written_entities("/batch", &batch),
+            vec![Entity::Event, Entity::Contract]
+        );
+        let archive = json!({"archive": {"contracts": [], "events": [{"event_id": "e1"}]}});
+        assert_eq!(written_entities("/import", &archive), vec![Entity::Event]);
+        assert!(written_entities("/login", &Value::Null).is_empty());
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(may_write(Role::Attestor, Entity::Event));
+        assert!(!may_write(Role::Attestor, Entity::Contract));
+        for role in [Role::RouterWallet, Role::Wallet] {
+            assert!(may_write(role, Entity::Contract));
+            assert!(!may_write(role, Entity::Event));
+        }
+        assert!(may_write_in_bulk(Role::RouterWallet));
+        assert!(!may_write_in_bulk(Role::Wallet));
+    }
+
+    #[test]
+    fn test_bulk_writes() {
+        assert!(is_bulk(&Method::POST, "/batch"));
+        assert!(is_bulk(&Method::POST, "/import"));
+        assert!(is_bulk(&Method::DELETE, "/contracts/k1"));
+        assert!(is_bulk(&Method::DELETE, "/events/k1"));
+        assert!(!is_bulk(&Method::DELETE, "/contract"));
+        assert!(!is_bulk(&Method::POST, "/contracts"));
+    }
+}
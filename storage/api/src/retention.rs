@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use actix_web::web::Data;
+use dlc_storage_common::models::PurgeSummary;
+use log::{error, info};
+
+use crate::config::Config;
+use crate::db::{self, Pools};
+use crate::error::ApiError;
+
+/// How long soft deleted and closed rows are kept before the retention job purges them,
+/// and how much of the change feed.
+#[derive(Clone, Copy, Debug)]
+pub struct Retention {
+    pub interval: Duration,
+    pub deleted_after: Duration,
+    /// `None` keeps closed contracts until they are deleted.
+    pub closed_after: Option<Duration>,
+    /// `None` keeps every change.
+    pub max_changes_per_key: Option<u64>,
+}
+
+impl Retention {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            interval: Duration::from_secs(config.retention_interval_secs),
+            deleted_after: Duration::from_secs(config.purge_deleted_after_secs),
+            closed_after: (config.purge_closed_after_secs > 0)
+                .then(|| Duration::from_secs(config.purge_closed_after_secs)),
+            max_changes_per_key: (config.max_changes_per_key > 0)
+                .then_some(config.max_changes_per_key),
+        }
+    }
+
+    /// Purges rows deleted more than `deleted_after` ago and contracts closed more than
+    /// `closed_after` ago, as of `now`, and trims the change feed of every key to
+    /// `max_changes_per_key`.
+    pub async fn purge(&self, pools: Data<Pools>, now: i64) -> Result<PurgeSummary, ApiError> {
+        let before = |age: Duration| now.saturating_sub(age.as_secs() as i64);
+        let deleted_before = before(self.deleted_after);
+        let closed_before = self.closed_after.map(before);
+        let max_changes = self
+            .max_changes_per_key
+            .map(|max| i64::try_from(max).unwrap_or(i64::MAX));
+        db::write_keys(
+            pools,
+            "purge",
+            move |conn| dlc_storage_writer::purge(conn, deleted_before, closed_before, max_changes),
+            |summary| &summary.keys,
+        )
+        .await
+    }
+}
+
+/// Runs the retention job every `interval` on the current runtime, unless it is 0.
+pub fn spawn(retention: Retention, pools: Data<Pools>) {
+    if retention.interval.is_zero() {
+        info!("Retention job disabled, deleted rows are kept until purged by hand");
+        return;
+    }
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(retention.interval);
+        loop {
+            interval.tick().await;
+            match retention
+                .purge(pools.clone(), dlc_storage_common::now())
+                .await
+            {
+                Ok(summary) if !summary.keys.is_empty() => info!(
+                    "Retention job purged {} contracts, {} events and {} changes from {} keys",
+                    summary.contracts,
+                    summary.events,
+                    summary.changes,
+                    summary.keys.len()
+                ),
+                Ok(_) => {}
+                Err(e) => error!("Retention job failed: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::ApiError;
+use crate::openapi::{ErrorBody, Session};
+use crate::verify_sigs::SignedBy;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web::Data;
+use actix_web::{post, HttpMessage, HttpRequest, HttpResponse};
+use rand::distributions::{Alphanumeric, DistString};
+use secp256k1::rand;
+use serde_json::json;
+
+/// Past this many live sessions, logging in ends the oldest one.
+const MAX_SESSIONS: usize = 10_000;
+
+/// Session tokens issued by /login. A token stands in for a signature on requests for
+/// the key that logged in, until it expires.
+pub struct Sessions {
+    tokens: HashMap<String, (String, Instant)>,
+    ttl: Duration,
+}
+
+impl Sessions {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            tokens: HashMap::new(),
+            ttl,
+        }
+    }
+
+    fn issue(&mut self, key: String) -> String {
+        let ttl = self.ttl;
+        self.tokens
+            .retain(|_, (_, issued_at)| issued_at.elapsed() < ttl);
+        if self.tokens.len() >= MAX_SESSIONS {
+            if let Some(oldest) = self
+                .tokens
+                .iter()
+                .min_by_key(|(_, (_, issued_at))| *issued_at)
+                .map(|(token, _)| token.clone())
+            {
+                self.tokens.remove(&oldest);
+            }
+        }
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+        self.tokens.insert(token.clone(), (key, Instant::now()));
+        token
+    }
+
+    /// The key `token` was issued for, unless it is unknown or expired.
+    pub fn key_of(&self, token: &str) -> Option<&str> {
+        match self.tokens.get(token) {
+            Some((key, issued_at)) if issued_at.elapsed() < self.ttl => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// Exchanges a signed message for a session token of the key that signed it.
+#[utoipa::path(
+    tag = "auth",
+    responses(
+        (status = 200, description = "A session token for the key that signed the message", body = Session),
+        (status = "4XX", description = "Rejected, see the error code", body = ErrorBody)
+    )
+)]
+#[post("/login")]
+pub async fn login(
+    req: HttpRequest,
+    sessions: Data<Mutex<Sessions>>,
+) -> Result<HttpResponse, ApiError> {
+    // a session must not extend itself, only a signature can start one
+    let by_session = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.starts_with("Bearer "));
+    let key = match req.extensions().get::<SignedBy>() {
+        Some(SignedBy(key)) if !by_session => key.clone(),
+        _ => {
+            return Err(ApiError::Auth(
+                "login has to be a signed message".to_string(),
+            ))
+        }
+    };
+    let mut sessions = sessions.lock().expect("Failed to lock sessions");
+    let token = sessions.issue(key.clone());
+    Ok(HttpResponse::Ok().json(json!({
+        "token": token,
+        "key": key,
+        "expires_in_secs": sessions.ttl.as_secs(),
+    })))
+}
@@ -0,0 +1,8 @@
+DROP INDEX events_key_maturity;
+DROP INDEX events_key_attested;
+DROP INDEX events_key_chain;
+ALTER TABLE events
+    DROP COLUMN maturity,
+    DROP COLUMN attested,
+    DROP COLUMN outcome,
+    DROP COLUMN chain;
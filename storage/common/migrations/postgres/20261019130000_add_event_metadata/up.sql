@@ -0,0 +1,8 @@
+ALTER TABLE events
+    ADD COLUMN maturity BIGINT,
+    ADD COLUMN attested BOOLEAN,
+    ADD COLUMN outcome VARCHAR,
+    ADD COLUMN chain VARCHAR;
+CREATE INDEX events_key_maturity ON events (key, maturity);
+CREATE INDEX events_key_attested ON events (key, attested);
+CREATE INDEX events_key_chain ON events (key, chain);
@@ -0,0 +1,9 @@
+DROP INDEX contracts_key_oracle_event_id;
+DROP INDEX contracts_key_funding_txid;
+DROP INDEX contracts_key_counterparty;
+DROP INDEX contracts_key_collateral;
+ALTER TABLE contracts
+    DROP COLUMN oracle_event_id,
+    DROP COLUMN funding_txid,
+    DROP COLUMN counterparty,
+    DROP COLUMN collateral;
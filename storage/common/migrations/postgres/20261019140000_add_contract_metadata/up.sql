@@ -0,0 +1,9 @@
+ALTER TABLE contracts
+    ADD COLUMN oracle_event_id VARCHAR,
+    ADD COLUMN funding_txid VARCHAR,
+    ADD COLUMN counterparty VARCHAR,
+    ADD COLUMN collateral BIGINT;
+CREATE INDEX contracts_key_oracle_event_id ON contracts (key, oracle_event_id);
+CREATE INDEX contracts_key_funding_txid ON contracts (key, funding_txid);
+CREATE INDEX contracts_key_counterparty ON contracts (key, counterparty);
+CREATE INDEX contracts_key_collateral ON contracts (key, collateral);
@@ -0,0 +1,8 @@
+ALTER TABLE contracts
+    ADD COLUMN deleted_at BIGINT,
+    ADD COLUMN updated_at BIGINT;
+UPDATE contracts SET updated_at = EXTRACT(EPOCH FROM NOW())::BIGINT;
+ALTER TABLE events ADD COLUMN deleted_at BIGINT;
+CREATE INDEX contracts_deleted_at ON contracts (deleted_at);
+CREATE INDEX contracts_state_updated_at ON contracts (state, updated_at);
+CREATE INDEX events_deleted_at ON events (deleted_at);
@@ -0,0 +1,5 @@
+CREATE TABLE registered_keys (
+    key VARCHAR PRIMARY KEY,
+    role VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL
+);
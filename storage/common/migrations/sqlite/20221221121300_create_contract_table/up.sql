@@ -0,0 +1,8 @@
+CREATE TABLE contracts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR NOT NULL,
+    state VARCHAR NOT NULL,
+    content TEXT NOT NULL,
+    key VARCHAR NOT NULL,
+    UNIQUE (key, uuid)
+);
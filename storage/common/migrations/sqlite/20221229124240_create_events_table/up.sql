@@ -0,0 +1,7 @@
+CREATE TABLE events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_id VARCHAR NOT NULL,
+    content TEXT NOT NULL,
+    key VARCHAR NOT NULL,
+    UNIQUE (key, event_id)
+);
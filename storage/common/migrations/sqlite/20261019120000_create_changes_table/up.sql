@@ -0,0 +1,9 @@
+CREATE TABLE changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    key VARCHAR NOT NULL,
+    entity VARCHAR NOT NULL,
+    entity_id VARCHAR NOT NULL,
+    kind VARCHAR NOT NULL,
+    state VARCHAR
+);
+CREATE INDEX changes_key_id ON changes (key, id);
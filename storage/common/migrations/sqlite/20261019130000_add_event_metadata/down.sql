@@ -0,0 +1,7 @@
+DROP INDEX events_key_maturity;
+DROP INDEX events_key_attested;
+DROP INDEX events_key_chain;
+ALTER TABLE events DROP COLUMN maturity;
+ALTER TABLE events DROP COLUMN attested;
+ALTER TABLE events DROP COLUMN outcome;
+ALTER TABLE events DROP COLUMN chain;
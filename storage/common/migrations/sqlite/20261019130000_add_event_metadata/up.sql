@@ -0,0 +1,7 @@
+ALTER TABLE events ADD COLUMN maturity BIGINT;
+ALTER TABLE events ADD COLUMN attested BOOLEAN;
+ALTER TABLE events ADD COLUMN outcome VARCHAR;
+ALTER TABLE events ADD COLUMN chain VARCHAR;
+CREATE INDEX events_key_maturity ON events (key, maturity);
+CREATE INDEX events_key_attested ON events (key, attested);
+CREATE INDEX events_key_chain ON events (key, chain);
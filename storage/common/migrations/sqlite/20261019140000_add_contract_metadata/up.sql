@@ -0,0 +1,8 @@
+ALTER TABLE contracts ADD COLUMN oracle_event_id VARCHAR;
+ALTER TABLE contracts ADD COLUMN funding_txid VARCHAR;
+ALTER TABLE contracts ADD COLUMN counterparty VARCHAR;
+ALTER TABLE contracts ADD COLUMN collateral BIGINT;
+CREATE INDEX contracts_key_oracle_event_id ON contracts (key, oracle_event_id);
+CREATE INDEX contracts_key_funding_txid ON contracts (key, funding_txid);
+CREATE INDEX contracts_key_counterparty ON contracts (key, counterparty);
+CREATE INDEX contracts_key_collateral ON contracts (key, collateral);
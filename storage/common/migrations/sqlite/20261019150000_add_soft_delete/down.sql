@@ -0,0 +1,8 @@
+DROP INDEX contracts_deleted_at;
+DROP INDEX contracts_state_updated_at;
+DROP INDEX events_deleted_at;
+DELETE FROM contracts WHERE deleted_at IS NOT NULL;
+DELETE FROM events WHERE deleted_at IS NOT NULL;
+ALTER TABLE contracts DROP COLUMN deleted_at;
+ALTER TABLE contracts DROP COLUMN updated_at;
+ALTER TABLE events DROP COLUMN deleted_at;
@@ -0,0 +1,7 @@
+ALTER TABLE contracts ADD COLUMN deleted_at BIGINT;
+ALTER TABLE contracts ADD COLUMN updated_at BIGINT;
+UPDATE contracts SET updated_at = CAST(strftime('%s', 'now') AS INTEGER);
+ALTER TABLE events ADD COLUMN deleted_at BIGINT;
+CREATE INDEX contracts_deleted_at ON contracts (deleted_at);
+CREATE INDEX contracts_state_updated_at ON contracts (state, updated_at);
+CREATE INDEX events_deleted_at ON events (deleted_at);
@@ -0,0 +1,5 @@
+CREATE TABLE imported_archives (
+    nonce VARCHAR PRIMARY KEY,
+    key VARCHAR NOT NULL,
+    imported_at BIGINT NOT NULL
+);
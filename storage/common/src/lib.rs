@@ -2,22 +2,108 @@ pub mod models;
 pub mod schema;
 
 use crate::models::*;
+use diesel::connection::SimpleConnection;
+use diesel::dsl::{count_star, max, sql};
 use diesel::expression_methods::ExpressionMethods;
 use diesel::query_dsl::QueryDsl;
-use diesel::RunQueryDsl;
-use diesel::{r2d2::Error, PgConnection};
+use diesel::r2d2::{ManageConnection, R2D2Connection};
+use diesel::sql_types::{BigInt, Integer};
+use diesel::{r2d2::Error, ConnectionError, ConnectionResult, PgConnection, SqliteConnection};
+use diesel::{Connection, OptionalExtension, RunQueryDsl};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use log::warn;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+/// Both backends share the same migration versions, only the SQL differs where the
+/// dialects do (e.g. `serial` vs `INTEGER PRIMARY KEY AUTOINCREMENT`).
+pub const PG_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
 
-pub fn run_migrations(conn: &mut PgConnection) -> Result<(), Error> {
-    conn.run_pending_migrations(MIGRATIONS).unwrap();
+/// A connection to either of the supported backends. Every query below is written once
+/// against this type.
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    Pg(PgConnection),
+    Sqlite(SqliteConnection),
+}
+
+/// Opens a connection, picking the backend from the url scheme:
+/// `postgres://` / `postgresql://` for Postgres, `sqlite://<path>` for SQLite
+/// (`sqlite://:memory:` for a throwaway in-memory database).
+pub fn establish(database_url: &str) -> ConnectionResult<DbConnection> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        return PgConnection::establish(database_url).map(DbConnection::Pg);
+    }
+    match database_url.strip_prefix("sqlite://") {
+        Some(path) => {
+            let mut conn = SqliteConnection::establish(path)?;
+            // several pooled connections share the file, wait for locks instead of failing
+            conn.batch_execute(
+                "PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000; PRAGMA foreign_keys = ON;",
+            )
+            .map_err(ConnectionError::CouldntSetupConfiguration)?;
+            Ok(DbConnection::Sqlite(conn))
+        }
+        None => Err(ConnectionError::InvalidConnectionUrl(format!(
+            "unsupported database url {}, expected postgres:// or sqlite://",
+            database_url
+        ))),
+    }
+}
+
+/// r2d2 connection manager for [`DbConnection`], so that the backend is chosen by
+/// [`establish`] rather than by trying each one in turn.
+#[derive(Debug, Clone)]
+pub struct DbConnectionManager {
+    database_url: String,
+}
+
+impl DbConnectionManager {
+    pub fn new(database_url: impl Into<String>) -> Self {
+        Self {
+            database_url: database_url.into(),
+        }
+    }
+}
+
+impl ManageConnection for DbConnectionManager {
+    type Connection = DbConnection;
+    type Error = Error;
+
+    fn connect(&self) -> Result<DbConnection, Error> {
+        establish(&self.database_url).map_err(Error::ConnectionError)
+    }
+
+    fn is_valid(&self, conn: &mut DbConnection) -> Result<(), Error> {
+        conn.ping().map_err(Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut DbConnection) -> bool {
+        std::thread::panicking() || conn.is_broken()
+    }
+}
+
+pub fn run_migrations(conn: &mut DbConnection) -> Result<(), Error> {
+    match conn {
+        DbConnection::Pg(conn) => conn.run_pending_migrations(PG_MIGRATIONS),
+        DbConnection::Sqlite(conn) => conn.run_pending_migrations(SQLITE_MIGRATIONS),
+    }
+    .unwrap();
     Ok(())
 }
 
+/// The number of migrations that have not been applied to the database yet.
+pub fn pending_migrations(
+    conn: &mut DbConnection,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    match conn {
+        DbConnection::Pg(conn) => conn.pending_migrations(PG_MIGRATIONS).map(|m| m.len()),
+        DbConnection::Sqlite(conn) => conn.pending_migrations(SQLITE_MIGRATIONS).map(|m| m.len()),
+    }
+}
+
 pub fn get_contracts(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract_params: ContractRequestParams,
 ) -> Result<Vec<Contract>, diesel::result::Error> {
     use crate::schema::contracts::dsl::*;
@@ -25,115 +111,231 @@ pub fn get_contracts(
     query = query.filter(key.eq(contract_params.key));
 
     if let Some(cstate) = contract_params.state {
-        query = query.filter(state.eq(cstate));
+        let states: Vec<String> = cstate.split(',').map(str::to_string).collect();
+        query = query.filter(state.eq_any(states));
     }
 
     if let Some(cuuid) = contract_params.uuid {
         query = query.filter(uuid.eq(cuuid));
     }
+    if let Some(coracle_event_id) = contract_params.oracle_event_id {
+        query = query.filter(oracle_event_id.eq(coracle_event_id));
+    }
+    if let Some(cfunding_txid) = contract_params.funding_txid {
+        query = query.filter(funding_txid.eq(cfunding_txid));
+    }
+    if let Some(ccounterparty) = contract_params.counterparty {
+        query = query.filter(counterparty.eq(ccounterparty));
+    }
+    if let Some(min) = contract_params.collateral_min {
+        query = query.filter(collateral.ge(min));
+    }
+    if let Some(max) = contract_params.collateral_max {
+        query = query.filter(collateral.le(max));
+    }
+    if contract_params.include_deleted != Some(true) {
+        query = query.filter(deleted_at.is_null());
+    }
 
     let results = query.load::<Contract>(conn)?;
     Ok(results)
 }
 
 pub fn create_contract(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract: NewContract,
 ) -> Result<Contract, diesel::result::Error> {
     use crate::schema::contracts::dsl::*;
-    match diesel::insert_into(contracts)
-        .values(&contract)
-        .get_result(conn)
-    {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            warn!("Got an error creating contract: {:?}", e);
-            Err(e)
-        }
+    let result = conn.transaction(|conn| {
+        // a soft deleted contract gives way to a new one with its uuid
+        diesel::delete(
+            contracts
+                .filter(key.eq(&contract.key))
+                .filter(uuid.eq(&contract.uuid))
+                .filter(deleted_at.is_not_null()),
+        )
+        .execute(conn)?;
+        let created: Contract = diesel::insert_into(contracts)
+            .values((&contract, updated_at.eq(now())))
+            .get_result(conn)?;
+        record_change(
+            conn,
+            NewChange {
+                key: &created.key,
+                entity: CONTRACT,
+                entity_id: &created.uuid,
+                kind: CREATED,
+                state: Some(&created.state),
+            },
+        )?;
+        Ok(created)
+    });
+    if let Err(e) = &result {
+        warn!("Got an error creating contract: {:?}", e);
     }
+    result
 }
 
 pub fn delete_contract(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract: DeleteContract,
 ) -> Result<usize, diesel::result::Error> {
     use crate::schema::contracts::dsl::*;
 
-    let num_deleted = diesel::delete(
-        contracts
-            .filter(uuid.eq(contract.uuid))
-            .filter(key.eq(contract.key)),
-    )
-    .execute(conn)?;
-
-    Ok(num_deleted)
+    conn.transaction(|conn| {
+        let num_deleted = diesel::update(
+            contracts
+                .filter(uuid.eq(&contract.uuid))
+                .filter(key.eq(&contract.key))
+                .filter(deleted_at.is_null()),
+        )
+        .set(deleted_at.eq(now()))
+        .execute(conn)?;
+        if num_deleted > 0 {
+            record_change(
+                conn,
+                NewChange {
+                    key: &contract.key,
+                    entity: CONTRACT,
+                    entity_id: &contract.uuid,
+                    kind: DELETED,
+                    state: None,
+                },
+            )?;
+        }
+        Ok(num_deleted)
+    })
 }
 
 pub fn delete_all_contracts(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     ckey: &str,
 ) -> Result<usize, diesel::result::Error> {
     use crate::schema::contracts::dsl::*;
 
-    let num_deleted = diesel::delete(contracts.filter(key.eq(ckey))).execute(conn)?;
-
-    Ok(num_deleted)
+    conn.transaction(|conn| {
+        let live = contracts.filter(key.eq(ckey)).filter(deleted_at.is_null());
+        let uuids = live.select(uuid).load::<String>(conn)?;
+        let num_deleted = diesel::update(live)
+            .set(deleted_at.eq(now()))
+            .execute(conn)?;
+        for cuuid in &uuids {
+            record_change(
+                conn,
+                NewChange {
+                    key: ckey,
+                    entity: CONTRACT,
+                    entity_id: cuuid,
+                    kind: DELETED,
+                    state: None,
+                },
+            )?;
+        }
+        Ok(num_deleted)
+    })
 }
 
 pub fn update_contract(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract: UpdateContract,
 ) -> Result<usize, diesel::result::Error> {
     use crate::schema::contracts::dsl::*;
-    let update_contract = contract.clone();
-    let num_updated = diesel::update(
-        contracts
-            .filter(uuid.eq(contract.uuid))
-            .filter(key.eq(contract.key)),
-    )
-    .set(&update_contract)
-    .execute(conn)?;
-    Ok(num_updated)
+    conn.transaction(|conn| {
+        let num_updated = diesel::update(
+            contracts
+                .filter(uuid.eq(&contract.uuid))
+                .filter(key.eq(&contract.key))
+                .filter(deleted_at.is_null()),
+        )
+        .set((&contract, updated_at.eq(now())))
+        .execute(conn)?;
+        if num_updated > 0 {
+            record_change(
+                conn,
+                NewChange {
+                    key: &contract.key,
+                    entity: CONTRACT,
+                    entity_id: &contract.uuid,
+                    kind: UPDATED,
+                    state: contract.state.as_deref(),
+                },
+            )?;
+        }
+        Ok(num_updated)
+    })
 }
 
 pub fn create_event(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event: NewEvent,
 ) -> Result<Event, diesel::result::Error> {
     use crate::schema::events::dsl::*;
-    match diesel::insert_into(events).values(&event).get_result(conn) {
-        Ok(event) => Ok(event),
-        Err(e) => {
-            warn!("Got an error creating event: {:?}", e);
-            Err(e)
-        }
+    let result = conn.transaction(|conn| {
+        // a soft deleted event gives way to a new one with its id
+        diesel::delete(
+            events
+                .filter(key.eq(&event.key))
+                .filter(event_id.eq(&event.event_id))
+                .filter(deleted_at.is_not_null()),
+        )
+        .execute(conn)?;
+        let created: Event = diesel::insert_into(events)
+            .values(&event)
+            .get_result(conn)?;
+        record_change(
+            conn,
+            NewChange {
+                key: &created.key,
+                entity: EVENT,
+                entity_id: &created.event_id,
+                kind: CREATED,
+                state: None,
+            },
+        )?;
+        Ok(created)
+    });
+    if let Err(e) = &result {
+        warn!("Got an error creating event: {:?}", e);
     }
+    result
 }
 
 pub fn update_event(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event: UpdateEvent,
 ) -> Result<usize, diesel::result::Error> {
     use crate::schema::events::dsl::*;
-    let update_event = event.clone();
-    match diesel::update(
-        events
-            .filter(event_id.eq(event.event_id))
-            .filter(key.eq(event.key)),
-    )
-    .set(&update_event)
-    .execute(conn)
-    {
-        Ok(num_updated) => Ok(num_updated),
-        Err(e) => {
-            warn!("Got an error creating event: {:?}", e);
-            Err(e)
+    let result = conn.transaction(|conn| {
+        let num_updated = diesel::update(
+            events
+                .filter(event_id.eq(&event.event_id))
+                .filter(key.eq(&event.key))
+                .filter(deleted_at.is_null()),
+        )
+        .set(&event)
+        .execute(conn)?;
+        if num_updated > 0 {
+            record_change(
+                conn,
+                NewChange {
+                    key: &event.key,
+                    entity: EVENT,
+                    entity_id: &event.event_id,
+                    kind: UPDATED,
+                    state: None,
+                },
+            )?;
         }
+        Ok(num_updated)
+    });
+    if let Err(e) = &result {
+        warn!("Got an error updating event: {:?}", e);
     }
+    result
 }
 
 pub fn get_events(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event: EventRequestParams,
 ) -> Result<Vec<Event>, diesel::result::Error> {
     use crate::schema::events::dsl::*;
@@ -143,30 +345,664 @@ pub fn get_events(
     if let Some(cevent_id) = event.event_id {
         query = query.filter(event_id.eq(cevent_id));
     }
+    if let Some(cattested) = event.attested {
+        query = query.filter(attested.eq(cattested));
+    }
+    if let Some(coutcome) = event.outcome {
+        query = query.filter(outcome.eq(coutcome));
+    }
+    if let Some(cchain) = event.chain {
+        query = query.filter(chain.eq(cchain));
+    }
+    if let Some(before) = event.matures_before {
+        query = query.filter(maturity.lt(before));
+    }
+    if let Some(after) = event.matures_after {
+        query = query.filter(maturity.gt(after));
+    }
+    if event.include_deleted != Some(true) {
+        query = query.filter(deleted_at.is_null());
+    }
 
     let results = query.load::<Event>(conn)?;
     Ok(results)
 }
 
 pub fn delete_event(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event: DeleteEvent,
 ) -> Result<usize, diesel::result::Error> {
     use crate::schema::events::dsl::*;
-    let num_deleted = diesel::delete(
-        events
-            .filter(event_id.eq(event.event_id))
-            .filter(key.eq(event.key)),
-    )
-    .execute(conn)?;
-    Ok(num_deleted)
+    conn.transaction(|conn| {
+        let num_deleted = diesel::update(
+            events
+                .filter(event_id.eq(&event.event_id))
+                .filter(key.eq(&event.key))
+                .filter(deleted_at.is_null()),
+        )
+        .set(deleted_at.eq(now()))
+        .execute(conn)?;
+        if num_deleted > 0 {
+            record_change(
+                conn,
+                NewChange {
+                    key: &event.key,
+                    entity: EVENT,
+                    entity_id: &event.event_id,
+                    kind: DELETED,
+                    state: None,
+                },
+            )?;
+        }
+        Ok(num_deleted)
+    })
 }
 
 pub fn delete_all_events(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     ckey: &str,
 ) -> Result<usize, diesel::result::Error> {
     use crate::schema::events::dsl::*;
-    let num_deleted = diesel::delete(events.filter(key.eq(ckey))).execute(conn)?;
-    Ok(num_deleted)
+    conn.transaction(|conn| {
+        let live = events.filter(key.eq(ckey)).filter(deleted_at.is_null());
+        let event_ids = live.select(event_id).load::<String>(conn)?;
+        let num_deleted = diesel::update(live)
+            .set(deleted_at.eq(now()))
+            .execute(conn)?;
+        for cevent_id in &event_ids {
+            record_change(
+                conn,
+                NewChange {
+                    key: ckey,
+                    entity: EVENT,
+                    entity_id: cevent_id,
+                    kind: DELETED,
+                    state: None,
+                },
+            )?;
+        }
+        Ok(num_deleted)
+    })
+}
+
+/// Undoes the soft delete of a contract.
+pub fn restore_contract(
+    conn: &mut DbConnection,
+    contract: DeleteContract,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::contracts::dsl::*;
+    conn.transaction(|conn| {
+        let deleted = contracts
+            .filter(uuid.eq(&contract.uuid))
+            .filter(key.eq(&contract.key))
+            .filter(deleted_at.is_not_null());
+        let Some(cstate) = deleted.select(state).first::<String>(conn).optional()? else {
+            return Ok(0);
+        };
+        let num_restored = diesel::update(deleted)
+            .set((deleted_at.eq(None::<i64>), updated_at.eq(now())))
+            .execute(conn)?;
+        record_change(
+            conn,
+            NewChange {
+                key: &contract.key,
+                entity: CONTRACT,
+                entity_id: &contract.uuid,
+                kind: CREATED,
+                state: Some(&cstate),
+            },
+        )?;
+        Ok(num_restored)
+    })
+}
+
+/// Undoes the soft delete of an event.
+pub fn restore_event(
+    conn: &mut DbConnection,
+    event: DeleteEvent,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::events::dsl::*;
+    conn.transaction(|conn| {
+        let num_restored = diesel::update(
+            events
+                .filter(event_id.eq(&event.event_id))
+                .filter(key.eq(&event.key))
+                .filter(deleted_at.is_not_null()),
+        )
+        .set(deleted_at.eq(None::<i64>))
+        .execute(conn)?;
+        if num_restored > 0 {
+            record_change(
+                conn,
+                NewChange {
+                    key: &event.key,
+                    entity: EVENT,
+                    entity_id: &event.event_id,
+                    kind: CREATED,
+                    state: None,
+                },
+            )?;
+        }
+        Ok(num_restored)
+    })
+}
+
+/// Removes for good the contracts and events soft deleted before `deleted_before`, and
+/// the contracts closed since before `closed_before`, if given (both unix timestamps).
+/// The change feed keeps only the deletion of a purged row, so subscribers that replay
+/// a key's history don't see more of it than they can load, and at most the last
+/// `max_changes_per_key` changes of a key, if given.
+pub fn purge(
+    conn: &mut DbConnection,
+    deleted_before: i64,
+    closed_before: Option<i64>,
+    max_changes_per_key: Option<i64>,
+) -> Result<PurgeSummary, diesel::result::Error> {
+    use crate::schema::{contracts, events};
+    conn.transaction(|conn| {
+        let mut summary = PurgeSummary::default();
+        let mut purged: Vec<(String, &str, String)> = Vec::new();
+
+        let deleted_contracts = contracts::table
+            .filter(contracts::deleted_at.lt(deleted_before))
+            .select((contracts::key, contracts::uuid))
+            .load::<(String, String)>(conn)?;
+        summary.contracts +=
+            diesel::delete(contracts::table.filter(contracts::deleted_at.lt(deleted_before)))
+                .execute(conn)?;
+        purged.extend(
+            deleted_contracts
+                .into_iter()
+                .map(|(ckey, cuuid)| (ckey, CONTRACT, cuuid)),
+        );
+
+        let deleted_events = events::table
+            .filter(events::deleted_at.lt(deleted_before))
+            .select((events::key, events::event_id))
+            .load::<(String, String)>(conn)?;
+        summary.events +=
+            diesel::delete(events::table.filter(events::deleted_at.lt(deleted_before)))
+                .execute(conn)?;
+        purged.extend(
+            deleted_events
+                .into_iter()
+                .map(|(ckey, cevent_id)| (ckey, EVENT, cevent_id)),
+        );
+
+        if let Some(closed_before) = closed_before {
+            let closed = contracts::table
+                .filter(contracts::state.eq(CLOSED))
+                .filter(contracts::updated_at.lt(closed_before))
+                .filter(contracts::deleted_at.is_null());
+            let closed_contracts =
+                closed
+                    .select((contracts::key, contracts::uuid))
+                    .load::<(String, String)>(conn)?;
+            summary.contracts += diesel::delete(closed).execute(conn)?;
+            for (ckey, cuuid) in &closed_contracts {
+                record_change(
+                    conn,
+                    NewChange {
+                        key: ckey,
+                        entity: CONTRACT,
+                        entity_id: cuuid,
+                        kind: DELETED,
+                        state: None,
+                    },
+                )?;
+            }
+            purged.extend(
+                closed_contracts
+                    .into_iter()
+                    .map(|(ckey, cuuid)| (ckey, CONTRACT, cuuid)),
+            );
+        }
+
+        for (ckey, centity, centity_id) in &purged {
+            summary.changes += prune_changes(conn, ckey, centity, centity_id)?;
+        }
+        summary.keys = purged.into_iter().map(|(ckey, _, _)| ckey).collect();
+        if let Some(max_changes) = max_changes_per_key {
+            let (trimmed, keys) = trim_changes(conn, max_changes)?;
+            summary.changes += trimmed;
+            summary.keys.extend(keys);
+        }
+        summary.keys.sort();
+        summary.keys.dedup();
+        Ok(summary)
+    })
+}
+
+/// Drops the changes of a contract or event but the last one, its deletion.
+fn prune_changes(
+    conn: &mut DbConnection,
+    ckey: &str,
+    centity: &str,
+    centity_id: &str,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::changes::dsl::*;
+    let of_entity = changes
+        .filter(key.eq(ckey))
+        .filter(entity.eq(centity))
+        .filter(entity_id.eq(centity_id));
+    match of_entity.select(max(id)).first::<Option<i32>>(conn)? {
+        Some(last) => diesel::delete(of_entity.filter(id.lt(last))).execute(conn),
+        None => Ok(0),
+    }
+}
+
+/// Drops the changes of every key with more than `max_changes` of them but the last
+/// `max_changes`. Returns how many were dropped, and from which keys.
+fn trim_changes(
+    conn: &mut DbConnection,
+    max_changes: i64,
+) -> Result<(usize, Vec<String>), diesel::result::Error> {
+    use crate::schema::changes::dsl::*;
+    let over = changes
+        .group_by(key)
+        .having(count_star().gt(max_changes))
+        .select(key)
+        .load::<String>(conn)?;
+    let mut trimmed = 0;
+    for ckey in &over {
+        let oldest_kept = changes
+            .filter(key.eq(ckey))
+            .order(id.desc())
+            .offset(max_changes - 1)
+            .select(id)
+            .first::<i32>(conn)?;
+        trimmed += diesel::delete(changes.filter(key.eq(ckey)).filter(id.lt(oldest_kept)))
+            .execute(conn)?;
+    }
+    Ok((trimmed, over))
+}
+
+/// Applies the operations of a batch in one transaction. Updates and deletes that
+/// match nothing fail with `NotFound`, like their endpoints do, and any failure rolls
+/// back the whole batch.
+pub fn apply_batch(
+    conn: &mut DbConnection,
+    operations: Vec<BatchOperation>,
+) -> Result<Vec<BatchResult>, BatchFailure> {
+    let effected = |effected_num: usize| match effected_num {
+        0 => Err(diesel::result::Error::NotFound),
+        _ => Ok(BatchResult::Effected { effected_num }),
+    };
+    let mut failed_at = None;
+    conn.transaction(|conn| {
+        let mut results = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.into_iter().enumerate() {
+            failed_at = Some(index);
+            results.push(match operation {
+                BatchOperation::CreateContract(contract) => {
+                    BatchResult::Contract(create_contract(conn, contract)?)
+                }
+                BatchOperation::UpdateContract(contract) => {
+                    effected(update_contract(conn, contract)?)?
+                }
+                BatchOperation::DeleteContract(contract) => {
+                    effected(delete_contract(conn, contract)?)?
+                }
+                BatchOperation::CreateEvent(event) => {
+                    BatchResult::Event(create_event(conn, event)?)
+                }
+                BatchOperation::UpdateEvent(event) => effected(update_event(conn, event)?)?,
+                BatchOperation::DeleteEvent(event) => effected(delete_event(conn, event)?)?,
+            });
+        }
+        failed_at = None;
+        Ok(results)
+    })
+    .map_err(|error| BatchFailure {
+        index: failed_at,
+        error,
+    })
+}
+
+/// The current time as a unix timestamp, for `deleted_at` and `updated_at`.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Everything stored for `ckey`, as an archive that can be imported once, by `cnonce`.
+pub fn export_key(
+    conn: &mut DbConnection,
+    ckey: &str,
+    cnonce: String,
+) -> Result<Archive, diesel::result::Error> {
+    let contracts = get_contracts(
+        conn,
+        ContractRequestParams {
+            key: ckey.to_string(),
+            ..Default::default()
+        },
+    )?;
+    let events = get_events(
+        conn,
+        EventRequestParams {
+            key: ckey.to_string(),
+            ..Default::default()
+        },
+    )?;
+    Ok(Archive {
+        version: ARCHIVE_VERSION,
+        key: ckey.to_string(),
+        exported_at: now(),
+        nonce: cnonce,
+        contracts: contracts
+            .into_iter()
+            .map(|contract| ArchivedContract {
+                uuid: contract.uuid,
+                state: contract.state,
+                content: contract.content,
+                oracle_event_id: contract.oracle_event_id,
+                funding_txid: contract.funding_txid,
+                counterparty: contract.counterparty,
+                collateral: contract.collateral,
+            })
+            .collect(),
+        events: events
+            .into_iter()
+            .map(|event| ArchivedEvent {
+                event_id: event.event_id,
+                content: event.content,
+                maturity: event.maturity,
+                attested: event.attested,
+                outcome: event.outcome,
+                chain: event.chain,
+            })
+            .collect(),
+    })
+}
+
+/// Restores an archive: creates what is missing, overwrites what differs and leaves
+/// everything else (including contracts and events not in the archive) alone, so
+/// importing the same contents twice is a no-op. The archive's nonce is recorded first,
+/// so importing the same archive again fails with a unique violation.
+pub fn import_archive(
+    conn: &mut DbConnection,
+    archive: &Archive,
+) -> Result<ImportSummary, diesel::result::Error> {
+    conn.transaction(|conn| {
+        diesel::insert_into(crate::schema::imported_archives::table)
+            .values(ImportedArchive {
+                nonce: &archive.nonce,
+                key: &archive.key,
+                imported_at: now(),
+            })
+            .execute(conn)?;
+        let mut summary = ImportSummary::default();
+        for archived in &archive.contracts {
+            let existing = get_contracts(
+                conn,
+                ContractRequestParams {
+                    key: archive.key.clone(),
+                    uuid: Some(archived.uuid.clone()),
+                    ..Default::default()
+                },
+            )?;
+            match existing.first() {
+                None => {
+                    create_contract(
+                        conn,
+                        NewContract {
+                            uuid: archived.uuid.clone(),
+                            state: archived.state.clone(),
+                            content: archived.content.clone(),
+                            key: archive.key.clone(),
+                            oracle_event_id: archived.oracle_event_id.clone(),
+                            funding_txid: archived.funding_txid.clone(),
+                            counterparty: archived.counterparty.clone(),
+                            collateral: archived.collateral,
+                        },
+                    )?;
+                    summary.created += 1;
+                }
+                Some(contract)
+                    if contract.state == archived.state
+                        && contract.content == archived.content
+                        && is_kept(&contract.oracle_event_id, &archived.oracle_event_id)
+                        && is_kept(&contract.funding_txid, &archived.funding_txid)
+                        && is_kept(&contract.counterparty, &archived.counterparty)
+                        && is_kept(&contract.collateral, &archived.collateral) =>
+                {
+                    summary.unchanged += 1;
+                }
+                Some(_) => {
+                    update_contract(
+                        conn,
+                        UpdateContract {
+                            uuid: archived.uuid.clone(),
+                            state: Some(archived.state.clone()),
+                            content: Some(archived.content.clone()),
+                            key: archive.key.clone(),
+                            oracle_event_id: archived.oracle_event_id.clone(),
+                            funding_txid: archived.funding_txid.clone(),
+                            counterparty: archived.counterparty.clone(),
+                            collateral: archived.collateral,
+                        },
+                    )?;
+                    summary.updated += 1;
+                }
+            }
+        }
+        for archived in &archive.events {
+            let existing = get_events(
+                conn,
+                EventRequestParams {
+                    key: archive.key.clone(),
+                    event_id: Some(archived.event_id.clone()),
+                    ..Default::default()
+                },
+            )?;
+            match existing.first() {
+                None => {
+                    create_event(
+                        conn,
+                        NewEvent {
+                            event_id: archived.event_id.clone(),
+                            content: archived.content.clone(),
+                            key: archive.key.clone(),
+                            maturity: archived.maturity,
+                            attested: archived.attested,
+                            outcome: archived.outcome.clone(),
+                            chain: archived.chain.clone(),
+                        },
+                    )?;
+                    summary.created += 1;
+                }
+                Some(event)
+                    if event.content == archived.content
+                        && is_kept(&event.maturity, &archived.maturity)
+                        && is_kept(&event.attested, &archived.attested)
+                        && is_kept(&event.outcome, &archived.outcome)
+                        && is_kept(&event.chain, &archived.chain) =>
+                {
+                    summary.unchanged += 1
+                }
+                Some(_) => {
+                    update_event(
+                        conn,
+                        UpdateEvent {
+                            event_id: archived.event_id.clone(),
+                            content: archived.content.clone(),
+                            key: archive.key.clone(),
+                            maturity: archived.maturity,
+                            attested: archived.attested,
+                            outcome: archived.outcome.clone(),
+                            chain: archived.chain.clone(),
+                        },
+                    )?;
+                    summary.updated += 1;
+                }
+            }
+        }
+        Ok(summary)
+    })
+}
+
+/// Whether an import leaves a metadata field as it is: fields missing from the archive
+/// are not touched, like in any other update.
+fn is_kept<T: PartialEq>(stored: &Option<T>, archived: &Option<T>) -> bool {
+    archived.is_none() || stored == archived
+}
+
+/// Every write above records what it did in the `changes` table, in the same
+/// transaction, for the change feed.
+fn record_change(conn: &mut DbConnection, change: NewChange) -> Result<(), diesel::result::Error> {
+    use crate::schema::changes::dsl::*;
+    diesel::insert_into(changes).values(&change).execute(conn)?;
+    Ok(())
+}
+
+/// The changes of `ckey` after the cursor `after`, oldest first, at most `limit` of them.
+pub fn get_changes(
+    conn: &mut DbConnection,
+    ckey: &str,
+    after: i32,
+    limit: i64,
+) -> Result<Vec<Change>, diesel::result::Error> {
+    use crate::schema::changes::dsl::*;
+    changes
+        .filter(key.eq(ckey))
+        .filter(id.gt(after))
+        .order(id.asc())
+        .limit(limit)
+        .load::<Change>(conn)
+}
+
+pub fn get_usage(conn: &mut DbConnection, ckey: &str) -> Result<Usage, diesel::result::Error> {
+    use crate::schema::{contracts, events};
+    let content_length = || sql::<BigInt>("COALESCE(SUM(LENGTH(content)), 0)");
+    let contracts = contracts::table
+        .filter(contracts::key.eq(ckey))
+        .filter(contracts::deleted_at.is_null())
+        .select((count_star(), content_length()))
+        .get_result::<(i64, i64)>(conn)?;
+    let events = events::table
+        .filter(events::key.eq(ckey))
+        .filter(events::deleted_at.is_null())
+        .select((count_star(), content_length()))
+        .get_result::<(i64, i64)>(conn)?;
+    Ok(Usage {
+        rows: contracts.0 + events.0,
+        content_bytes: contracts.1 + events.1,
+    })
+}
+
+/// The content length of those of the contracts `uuids` and events `event_ids` of `ckey`
+/// that are stored, deleted or not.
+pub fn get_content_lengths(
+    conn: &mut DbConnection,
+    ckey: &str,
+    uuids: &[String],
+    event_ids: &[String],
+) -> Result<ContentLengths, diesel::result::Error> {
+    use crate::schema::{contracts, events};
+    let content_length = || sql::<Integer>("LENGTH(content)");
+    let stored = |(id, length, deleted_at): (String, i32, Option<i64>)| {
+        let length = StoredLength {
+            length: i64::from(length),
+            deleted: deleted_at.is_some(),
+        };
+        (id, length)
+    };
+    let mut lengths = ContentLengths::default();
+    if !uuids.is_empty() {
+        lengths.contracts = contracts::table
+            .filter(contracts::key.eq(ckey))
+            .filter(contracts::uuid.eq_any(uuids))
+            .select((contracts::uuid, content_length(), contracts::deleted_at))
+            .load(conn)?
+            .into_iter()
+            .map(stored)
+            .collect();
+    }
+    if !event_ids.is_empty() {
+        lengths.events = events::table
+            .filter(events::key.eq(ckey))
+            .filter(events::event_id.eq_any(event_ids))
+            .select((events::event_id, content_length(), events::deleted_at))
+            .load(conn)?
+            .into_iter()
+            .map(stored)
+            .collect();
+    }
+    Ok(lengths)
+}
+
+pub fn get_stats(conn: &mut DbConnection) -> Result<Stats, diesel::result::Error> {
+    use crate::schema::{contracts, events};
+    let contracts_by_state = contracts::table
+        .filter(contracts::deleted_at.is_null())
+        .group_by(contracts::state)
+        .select((contracts::state, count_star()))
+        .order(contracts::state)
+        .load::<(String, i64)>(conn)?;
+    let events_by_attested = events::table
+        .filter(events::deleted_at.is_null())
+        .group_by(events::attested)
+        .select((events::attested, count_star()))
+        .load::<(Option<bool>, i64)>(conn)?;
+    let count = |attested: bool| {
+        events_by_attested
+            .iter()
+            .filter(|(a, _)| a.unwrap_or(false) == attested)
+            .map(|(_, n)| n)
+            .sum()
+    };
+    Ok(Stats {
+        contracts_by_state,
+        attested_events: count(true),
+        unattested_events: count(false),
+    })
+}
+
+pub fn get_registered_key(
+    conn: &mut DbConnection,
+    ckey: &str,
+) -> Result<Option<RegisteredKey>, diesel::result::Error> {
+    use crate::schema::registered_keys::dsl::*;
+    registered_keys
+        .filter(key.eq(ckey))
+        .first::<RegisteredKey>(conn)
+        .optional()
+}
+
+pub fn get_registered_keys(
+    conn: &mut DbConnection,
+) -> Result<Vec<RegisteredKey>, diesel::result::Error> {
+    use crate::schema::registered_keys::dsl::*;
+    registered_keys.order(key).load::<RegisteredKey>(conn)
+}
+
+/// Registers `ckey` with `crole`, or changes the role of a registered key.
+pub fn register_key(
+    conn: &mut DbConnection,
+    ckey: &str,
+    crole: &str,
+) -> Result<RegisteredKey, diesel::result::Error> {
+    use crate::schema::registered_keys::dsl::*;
+    conn.transaction(|conn| {
+        let updated = diesel::update(registered_keys.filter(key.eq(ckey)))
+            .set(role.eq(crole))
+            .execute(conn)?;
+        if updated == 0 {
+            diesel::insert_into(registered_keys)
+                .values(RegisteredKey {
+                    key: ckey.to_string(),
+                    role: crole.to_string(),
+                    created_at: now(),
+                })
+                .execute(conn)?;
+        }
+        registered_keys.filter(key.eq(ckey)).first(conn)
+    })
+}
+
+pub fn unregister_key(conn: &mut DbConnection, ckey: &str) -> Result<usize, diesel::result::Error> {
+    use crate::schema::registered_keys::dsl::*;
+    diesel::delete(registered_keys.filter(key.eq(ckey))).execute(conn)
 }
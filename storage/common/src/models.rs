@@ -1,81 +1,457 @@
 use super::schema::*;
+use std::collections::HashMap;
+
 use diesel::{AsChangeset, Insertable, Queryable};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Insertable, Serialize, Deserialize, Queryable, Debug)]
+/// Like events, contracts can carry metadata in indexed columns next to their content:
+/// the id of the oracle event they settle on, the funding transaction id, the
+/// counterparty's public key and the total collateral in sats.
+#[derive(Insertable, Serialize, Deserialize, Queryable, Debug, ToSchema)]
 #[diesel(table_name = contracts)]
 pub struct NewContract {
     pub uuid: String,
     pub state: String,
     pub content: String,
     pub key: String,
+    #[serde(default)]
+    pub oracle_event_id: Option<String>,
+    #[serde(default)]
+    pub funding_txid: Option<String>,
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    #[serde(default)]
+    pub collateral: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, Queryable, Debug)]
+#[derive(Serialize, Deserialize, Queryable, Debug, ToSchema)]
 pub struct Contract {
     pub id: i32,
     pub uuid: String,
     pub state: String,
     pub content: String,
     pub key: String,
+    pub oracle_event_id: Option<String>,
+    pub funding_txid: Option<String>,
+    pub counterparty: Option<String>,
+    pub collateral: Option<i64>,
+    /// When the contract was soft deleted, as a unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    /// When the contract was last written, as a unix timestamp.
+    #[serde(default)]
+    pub updated_at: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, AsChangeset, Debug, Clone)]
+/// Metadata left out of an update keeps its stored value.
+#[derive(Serialize, Deserialize, AsChangeset, Debug, Clone, ToSchema)]
 #[diesel(table_name = contracts)]
 pub struct UpdateContract {
     pub uuid: String,
     pub state: Option<String>,
     pub content: Option<String>,
     pub key: String,
+    #[serde(default)]
+    pub oracle_event_id: Option<String>,
+    #[serde(default)]
+    pub funding_txid: Option<String>,
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    #[serde(default)]
+    pub collateral: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, AsChangeset, Debug)]
+#[derive(Serialize, Deserialize, AsChangeset, Debug, ToSchema)]
 #[diesel(table_name = contracts)]
 pub struct DeleteContract {
     pub uuid: String,
     pub key: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// Filters of a GET /contracts. Metadata filters only match contracts that have the
+/// field set.
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ContractRequestParams {
     pub key: String,
     pub uuid: Option<String>,
+    /// One state, or several separated by commas, e.g. `signed,confirmed`.
     pub state: Option<String>,
+    pub oracle_event_id: Option<String>,
+    pub funding_txid: Option<String>,
+    pub counterparty: Option<String>,
+    /// Collateral of at least this many sats.
+    pub collateral_min: Option<i64>,
+    /// Collateral of at most this many sats.
+    pub collateral_max: Option<i64>,
+    /// Also return soft deleted contracts.
+    pub include_deleted: Option<bool>,
 }
 
-#[derive(Insertable, Serialize, Deserialize, Queryable, Debug)]
+/// Events can carry metadata next to their opaque content: the maturity as a unix
+/// timestamp, whether the event is attested, its outcome and the chain it belongs to.
+/// The metadata is stored in the clear, in indexed columns, so events can be filtered
+/// by it.
+#[derive(Insertable, Serialize, Deserialize, Queryable, Debug, ToSchema)]
 #[diesel(table_name = events)]
 pub struct NewEvent {
     pub event_id: String,
     pub content: String,
     pub key: String,
+    #[serde(default)]
+    pub maturity: Option<i64>,
+    #[serde(default)]
+    pub attested: Option<bool>,
+    #[serde(default)]
+    pub outcome: Option<String>,
+    #[serde(default)]
+    pub chain: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Queryable, Debug)]
+#[derive(Serialize, Deserialize, Queryable, Debug, ToSchema)]
 pub struct Event {
     pub id: i32,
     pub event_id: String,
     pub content: String,
     pub key: String,
+    pub maturity: Option<i64>,
+    pub attested: Option<bool>,
+    pub outcome: Option<String>,
+    pub chain: Option<String>,
+    /// When the event was soft deleted, as a unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, AsChangeset, Debug, Clone)]
+/// Metadata left out of an update keeps its stored value.
+#[derive(Serialize, Deserialize, AsChangeset, Debug, Clone, ToSchema)]
 #[diesel(table_name = events)]
 pub struct UpdateEvent {
     pub event_id: String,
     pub content: String,
     pub key: String,
+    #[serde(default)]
+    pub maturity: Option<i64>,
+    #[serde(default)]
+    pub attested: Option<bool>,
+    #[serde(default)]
+    pub outcome: Option<String>,
+    #[serde(default)]
+    pub chain: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, AsChangeset, Debug, Clone)]
+#[derive(Serialize, Deserialize, AsChangeset, Debug, Clone, ToSchema)]
 #[diesel(table_name = events)]
 pub struct DeleteEvent {
     pub event_id: String,
     pub key: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// Filters of a GET /events. Metadata filters only match events that have the field set.
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct EventRequestParams {
     pub key: String,
     pub event_id: Option<String>,
+    pub attested: Option<bool>,
+    pub outcome: Option<String>,
+    pub chain: Option<String>,
+    /// Maturity strictly before this unix timestamp.
+    pub matures_before: Option<i64>,
+    /// Maturity strictly after this unix timestamp.
+    pub matures_after: Option<i64>,
+    /// Also return soft deleted events.
+    pub include_deleted: Option<bool>,
+}
+
+/// Bulk deletes only go ahead with `confirm=true`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BulkDeleteParams {
+    pub confirm: Option<bool>,
+}
+
+/// A row of the change feed: what happened to which contract or event of a key.
+/// `id` is the cursor a subscriber resumes from.
+#[derive(Serialize, Deserialize, Queryable, Debug, Clone, PartialEq, ToSchema)]
+pub struct Change {
+    pub id: i32,
+    pub key: String,
+    pub entity: String,
+    pub entity_id: String,
+    pub kind: String,
+    pub state: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = changes)]
+pub struct NewChange<'a> {
+    pub key: &'a str,
+    pub entity: &'a str,
+    pub entity_id: &'a str,
+    pub kind: &'a str,
+    pub state: Option<&'a str>,
+}
+
+pub const CONTRACT: &str = "contract";
+pub const EVENT: &str = "event";
+
+pub const CREATED: &str = "created";
+pub const UPDATED: &str = "updated";
+pub const DELETED: &str = "deleted";
+
+/// Contracts in this state are done, the retention job can purge them after a while.
+pub const CLOSED: &str = "closed";
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangeRequestParams {
+    pub key: String,
+    /// Only changes after this cursor, from the start of the key's history if unset.
+    pub after: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportRequestParams {
+    pub key: String,
+}
+
+/// Version of the export archive format, bumped on incompatible changes.
+pub const ARCHIVE_VERSION: u32 = 2;
+
+/// Everything stored for a key, as exported by the API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Archive {
+    pub version: u32,
+    pub key: String,
+    /// When the archive was exported, as a unix timestamp. Old archives are refused, so
+    /// a leaked one can't roll the key back for long.
+    pub exported_at: i64,
+    /// Picked by the exporting API, an archive can only be imported once.
+    pub nonce: String,
+    pub contracts: Vec<ArchivedContract>,
+    pub events: Vec<ArchivedEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ArchivedContract {
+    pub uuid: String,
+    pub state: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oracle_event_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub funding_txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collateral: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ArchivedEvent {
+    pub event_id: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maturity: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attested: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+}
+
+/// An archive signed by its key, so it can't be tampered with between export and import.
+/// The signature is over the JSON serialization of `archive`.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SignedArchive {
+    pub archive: Archive,
+    pub signature: String,
+}
+
+/// An archive that was imported, so that it can't be imported again.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = imported_archives)]
+pub struct ImportedArchive<'a> {
+    pub nonce: &'a str,
+    pub key: &'a str,
+    pub imported_at: i64,
+}
+
+/// One write of a batch, with the body its own endpoint takes. Every operation has to
+/// be for the key of the batch.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    CreateContract(NewContract),
+    UpdateContract(UpdateContract),
+    DeleteContract(DeleteContract),
+    CreateEvent(NewEvent),
+    UpdateEvent(UpdateEvent),
+    DeleteEvent(DeleteEvent),
+}
+
+impl BatchOperation {
+    pub fn key(&self) -> &str {
+        match self {
+            BatchOperation::CreateContract(contract) => &contract.key,
+            BatchOperation::UpdateContract(contract) => &contract.key,
+            BatchOperation::DeleteContract(contract) => &contract.key,
+            BatchOperation::CreateEvent(event) => &event.key,
+            BatchOperation::UpdateEvent(event) => &event.key,
+            BatchOperation::DeleteEvent(event) => &event.key,
+        }
+    }
+}
+
+/// Writes to a key applied together in one transaction: all of them or none.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BatchRequest {
+    pub key: String,
+    pub operations: Vec<BatchOperation>,
+}
+
+/// What an operation of a batch returned, the same as its own endpoint would have.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Contract(Contract),
+    Event(Event),
+    Effected { effected_num: usize },
+}
+
+/// The results of a batch, in the order of its operations.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BatchResponse {
+    pub results: Vec<BatchResult>,
+}
+
+/// Why a batch was rolled back: the operation at `index` failed, or with no `index`,
+/// the transaction itself did.
+#[derive(Debug)]
+pub struct BatchFailure {
+    pub index: Option<usize>,
+    pub error: diesel::result::Error,
+}
+
+/// A public key the API serves when registration is required, and what it may write.
+#[derive(Insertable, Serialize, Deserialize, Queryable, Debug, Clone, PartialEq, ToSchema)]
+#[diesel(table_name = registered_keys)]
+pub struct RegisteredKey {
+    pub key: String,
+    /// `attestor`, `router_wallet` or `wallet`.
+    pub role: String,
+    /// When the key was registered, as a unix timestamp.
+    pub created_at: i64,
+}
+
+/// What a registered key is, which decides what it may write.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Writes events, no contracts.
+    Attestor,
+    /// Writes its own contracts, also in batches, imports and bulk deletes, no events.
+    RouterWallet,
+    /// Writes its own contracts one at a time, no events.
+    Wallet,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Attestor => "attestor",
+            Role::RouterWallet => "router_wallet",
+            Role::Wallet => "wallet",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "attestor" => Ok(Role::Attestor),
+            "router_wallet" => Ok(Role::RouterWallet),
+            "wallet" => Ok(Role::Wallet),
+            _ => Err(format!("unknown role {}", role)),
+        }
+    }
+}
+
+/// Registers `public_key` with `role`, signed by the admin key in `key`.
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct RegisterKey {
+    pub key: String,
+    pub public_key: String,
+    pub role: Role,
+}
+
+/// Removes `public_key` from the registered keys, signed by the admin key in `key`.
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UnregisterKey {
+    pub key: String,
+    pub public_key: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AdminRequestParams {
+    /// The admin key.
+    pub key: String,
+}
+
+/// What a retention run removed for good.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct PurgeSummary {
+    pub contracts: usize,
+    pub events: usize,
+    /// Rows of the change feed.
+    pub changes: usize,
+    /// The keys rows were purged from.
+    pub keys: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, ToSchema)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+/// What is stored across all keys, without deleted rows, for the metrics.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// How many contracts are in each state.
+    pub contracts_by_state: Vec<(String, i64)>,
+    pub attested_events: i64,
+    /// Events not marked attested, including those that don't say.
+    pub unattested_events: i64,
+}
+
+/// What a key stores, for quotas. `content_bytes` counts the characters of the
+/// contents.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub rows: i64,
+    pub content_bytes: i64,
+}
+
+/// The content length of the stored contracts and events a write is for, by uuid and
+/// event id, so a write is only charged what it adds.
+#[derive(Debug, Default, PartialEq)]
+pub struct ContentLengths {
+    pub contracts: HashMap<String, StoredLength>,
+    pub events: HashMap<String, StoredLength>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoredLength {
+    pub length: i64,
+    /// Deleted rows don't count towards the quotas until they are restored.
+    pub deleted: bool,
 }
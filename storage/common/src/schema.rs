@@ -7,6 +7,12 @@ diesel::table! {
         state -> Varchar,
         content -> Text,
         key -> Varchar,
+        oracle_event_id -> Nullable<Varchar>,
+        funding_txid -> Nullable<Varchar>,
+        counterparty -> Nullable<Varchar>,
+        collateral -> Nullable<Int8>,
+        deleted_at -> Nullable<Int8>,
+        updated_at -> Nullable<Int8>,
     }
 }
 
@@ -16,5 +22,37 @@ diesel::table! {
         event_id -> Varchar,
         content -> Text,
         key -> Varchar,
+        maturity -> Nullable<Int8>,
+        attested -> Nullable<Bool>,
+        outcome -> Nullable<Varchar>,
+        chain -> Nullable<Varchar>,
+        deleted_at -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    changes (id) {
+        id -> Int4,
+        key -> Varchar,
+        entity -> Varchar,
+        entity_id -> Varchar,
+        kind -> Varchar,
+        state -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    registered_keys (key) {
+        key -> Varchar,
+        role -> Varchar,
+        created_at -> Int8,
+    }
+}
+
+diesel::table! {
+    imported_archives (nonce) {
+        nonce -> Varchar,
+        key -> Varchar,
+        imported_at -> Int8,
     }
 }
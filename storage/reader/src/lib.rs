@@ -1,19 +1,72 @@
-use diesel::PgConnection;
+use dlc_storage_common::models::Archive;
+use dlc_storage_common::models::Change;
+use dlc_storage_common::models::ContentLengths;
 use dlc_storage_common::models::Contract;
 use dlc_storage_common::models::ContractRequestParams;
 use dlc_storage_common::models::Event;
 use dlc_storage_common::models::EventRequestParams;
+use dlc_storage_common::models::RegisteredKey;
+use dlc_storage_common::models::Stats;
+use dlc_storage_common::models::Usage;
+use dlc_storage_common::DbConnection;
 
 pub fn get_contracts(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract_params: ContractRequestParams,
 ) -> Result<Vec<Contract>, diesel::result::Error> {
     dlc_storage_common::get_contracts(conn, contract_params)
 }
 
 pub fn get_events(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event_params: EventRequestParams,
 ) -> Result<Vec<Event>, diesel::result::Error> {
     dlc_storage_common::get_events(conn, event_params)
 }
+
+pub fn get_changes(
+    conn: &mut DbConnection,
+    key: &str,
+    after: i32,
+    limit: i64,
+) -> Result<Vec<Change>, diesel::result::Error> {
+    dlc_storage_common::get_changes(conn, key, after, limit)
+}
+
+pub fn export_key(
+    conn: &mut DbConnection,
+    key: &str,
+    nonce: String,
+) -> Result<Archive, diesel::result::Error> {
+    dlc_storage_common::export_key(conn, key, nonce)
+}
+
+pub fn get_usage(conn: &mut DbConnection, key: &str) -> Result<Usage, diesel::result::Error> {
+    dlc_storage_common::get_usage(conn, key)
+}
+
+pub fn get_content_lengths(
+    conn: &mut DbConnection,
+    key: &str,
+    uuids: &[String],
+    event_ids: &[String],
+) -> Result<ContentLengths, diesel::result::Error> {
+    dlc_storage_common::get_content_lengths(conn, key, uuids, event_ids)
+}
+
+pub fn get_stats(conn: &mut DbConnection) -> Result<Stats, diesel::result::Error> {
+    dlc_storage_common::get_stats(conn)
+}
+
+pub fn get_registered_key(
+    conn: &mut DbConnection,
+    key: &str,
+) -> Result<Option<RegisteredKey>, diesel::result::Error> {
+    dlc_storage_common::get_registered_key(conn, key)
+}
+
+pub fn get_registered_keys(
+    conn: &mut DbConnection,
+) -> Result<Vec<RegisteredKey>, diesel::result::Error> {
+    dlc_storage_common::get_registered_keys(conn)
+}
@@ -1,62 +1,118 @@
-use diesel::PgConnection;
 use dlc_storage_common::models::{
-    Contract, DeleteContract, DeleteEvent, Event, NewContract, NewEvent, UpdateContract,
+    Archive, BatchFailure, BatchOperation, BatchResult, Contract, DeleteContract, DeleteEvent,
+    Event, ImportSummary, NewContract, NewEvent, PurgeSummary, RegisteredKey, UpdateContract,
     UpdateEvent,
 };
+use dlc_storage_common::DbConnection;
 
-pub fn apply_migrations(conn: &mut PgConnection) {
+pub fn apply_migrations(conn: &mut DbConnection) {
     let _ = dlc_storage_common::run_migrations(conn);
 }
 
+pub fn pending_migrations(
+    conn: &mut DbConnection,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    dlc_storage_common::pending_migrations(conn)
+}
+
 pub fn create_contract(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract: NewContract,
 ) -> Result<Contract, diesel::result::Error> {
     dlc_storage_common::create_contract(conn, contract)
 }
 
 pub fn update_contract(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract: UpdateContract,
 ) -> Result<usize, diesel::result::Error> {
     dlc_storage_common::update_contract(conn, contract)
 }
 
 pub fn delete_contract(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     contract: DeleteContract,
 ) -> Result<usize, diesel::result::Error> {
     dlc_storage_common::delete_contract(conn, contract)
 }
 
 pub fn delete_all_contracts(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     ckey: &str,
 ) -> Result<usize, diesel::result::Error> {
     dlc_storage_common::delete_all_contracts(conn, ckey)
 }
 
 pub fn create_event(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event: NewEvent,
 ) -> Result<Event, diesel::result::Error> {
     dlc_storage_common::create_event(conn, event)
 }
 
 pub fn update_event(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event: UpdateEvent,
 ) -> Result<usize, diesel::result::Error> {
     dlc_storage_common::update_event(conn, event)
 }
 
 pub fn delete_event(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     event: DeleteEvent,
 ) -> Result<usize, diesel::result::Error> {
     dlc_storage_common::delete_event(conn, event)
 }
 
-pub fn delete_events(conn: &mut PgConnection, ckey: &str) -> Result<usize, diesel::result::Error> {
+pub fn delete_events(conn: &mut DbConnection, ckey: &str) -> Result<usize, diesel::result::Error> {
     dlc_storage_common::delete_all_events(conn, ckey)
 }
+
+pub fn import_archive(
+    conn: &mut DbConnection,
+    archive: &Archive,
+) -> Result<ImportSummary, diesel::result::Error> {
+    dlc_storage_common::import_archive(conn, archive)
+}
+
+pub fn apply_batch(
+    conn: &mut DbConnection,
+    operations: Vec<BatchOperation>,
+) -> Result<Vec<BatchResult>, BatchFailure> {
+    dlc_storage_common::apply_batch(conn, operations)
+}
+
+pub fn restore_contract(
+    conn: &mut DbConnection,
+    contract: DeleteContract,
+) -> Result<usize, diesel::result::Error> {
+    dlc_storage_common::restore_contract(conn, contract)
+}
+
+pub fn restore_event(
+    conn: &mut DbConnection,
+    event: DeleteEvent,
+) -> Result<usize, diesel::result::Error> {
+    dlc_storage_common::restore_event(conn, event)
+}
+
+pub fn purge(
+    conn: &mut DbConnection,
+    deleted_before: i64,
+    closed_before: Option<i64>,
+    max_changes_per_key: Option<i64>,
+) -> Result<PurgeSummary, diesel::result::Error> {
+    dlc_storage_common::purge(conn, deleted_before, closed_before, max_changes_per_key)
+}
+
+pub fn register_key(
+    conn: &mut DbConnection,
+    key: &str,
+    role: &str,
+) -> Result<RegisteredKey, diesel::result::Error> {
+    dlc_storage_common::register_key(conn, key, role)
+}
+
+pub fn unregister_key(conn: &mut DbConnection, key: &str) -> Result<usize, diesel::result::Error> {
+    dlc_storage_common::unregister_key(conn, key)
+}
@@ -23,7 +23,7 @@ use std::{collections::HashMap, env, str::FromStr, sync::Arc};
 
 use bitcoin::{Address, PublicKey, XOnlyPublicKey};
 
-use dlc_link_manager::{AsyncOracle, AsyncStorage, Manager, ONE_DAY_IN_SECONDS};
+use dlc_link_manager::{AsyncOracle, AsyncStorage, CachingStorage, Manager, ONE_DAY_IN_SECONDS};
 use dlc_manager::{
     contract::{
         contract_input::{ContractInput, ContractInputInfo, OracleInput},
@@ -58,10 +58,13 @@ impl fmt::Display for WalletError {
 }
 impl std::error::Error for WalletError {}
 static NOTFOUND: &[u8] = b"Not Found";
+// contracts are kept in memory only if STORAGE_API_CACHE_CONTRACTS says that the wallet
+// is their only writer
+type DlcStore = CachingStorage<AsyncStorageApiProvider>;
 type DlcManager<'a> = Manager<
     Arc<DlcWallet>,
     Arc<EsploraAsyncBlockchainProviderRouterWallet>,
-    Arc<AsyncStorageApiProvider>,
+    Arc<DlcStore>,
     Arc<AttestorClient>,
     Arc<SystemTimeProvider>,
 >;
@@ -207,7 +210,7 @@ fn build_error_response(message: String) -> Result<Response<Body>, GenericError>
 async fn process_request(
     req: hyper::Request<hyper::Body>,
     manager: Arc<DlcManager<'_>>,
-    dlc_store: Arc<AsyncStorageApiProvider>,
+    dlc_store: Arc<DlcStore>,
     wallet: Arc<DlcWallet>,
     active_network: String,
     blockchain_interface_url: String,
@@ -394,6 +397,12 @@ async fn main() -> Result<(), GenericError> {
 
     let storage_api_url = env::var("STORAGE_API_ENDPOINT")
         .expect("STORAGE_API_ENDPOINT environment variable not set");
+    let storage_api_encrypt_content =
+        env::var("STORAGE_API_ENCRYPT_CONTENT").is_ok_and(|value| value == "true");
+    let storage_api_plaintext_metadata =
+        env::var("STORAGE_API_PLAINTEXT_METADATA").is_ok_and(|value| value == "true");
+    let storage_api_cache_contracts =
+        env::var("STORAGE_API_CACHE_CONTRACTS").is_ok_and(|value| value == "true");
     let electrs_host =
         env::var("ELECTRUM_API_URL").expect("ELECTRUM_API_URL environment variable not set"); // Set up Blockchain Connection Object
     let active_network: bitcoin::Network = match env::var("BITCOIN_NETWORK").as_deref() {
@@ -442,11 +451,19 @@ async fn main() -> Result<(), GenericError> {
     }
 
     // Set up DLC store
-    let dlc_store = Arc::new(AsyncStorageApiProvider::new(
-        pubkey.to_string(),
-        secret_key,
-        storage_api_url,
-    ));
+    let mut dlc_store =
+        AsyncStorageApiProvider::new(pubkey.to_string(), secret_key, storage_api_url);
+    if storage_api_encrypt_content {
+        dlc_store = dlc_store.with_content_encryption();
+    }
+    if storage_api_plaintext_metadata {
+        dlc_store = dlc_store.with_plaintext_metadata();
+    }
+    let dlc_store = Arc::new(if storage_api_cache_contracts {
+        CachingStorage::new(dlc_store)
+    } else {
+        CachingStorage::uncached(dlc_store)
+    });
 
     // Set up time provider
     let time_provider = SystemTimeProvider {};
@@ -653,7 +670,7 @@ async fn accept_offer(
 }
 
 async fn get_wallet_info(
-    store: Arc<AsyncStorageApiProvider>,
+    store: Arc<DlcStore>,
     wallet: Arc<DlcWallet>,
     // static_address: String,
 ) -> Result<Response<Body>, GenericError> {
@@ -718,7 +735,7 @@ async fn get_wallet_info(
 
 async fn periodic_check(
     manager: Arc<DlcManager<'_>>,
-    store: Arc<AsyncStorageApiProvider>,
+    store: Arc<DlcStore>,
     blockchain_interface_url: String,
 ) -> Result<String, GenericError> {
     let funded_url = format!("{}/set-status-funded", blockchain_interface_url);